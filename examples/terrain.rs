@@ -1,7 +1,11 @@
 use std::{cell::RefCell, rc::Rc};
 
 use gtk4::{cairo::Context, prelude::WidgetExt, DrawingArea};
-use terrain_attributes_builder::{drainage::map::DrainageMap, flatness::FlatnessMap};
+use terrain_attributes_builder::{
+    boundary::BoundaryPolicy,
+    drainage::map::DrainageMap,
+    flatness::{FlatnessMap, FlatnessMapBuilder, FlatnessMetric},
+};
 use vislayers::{
     colormap::SimpleColorMap,
     geometry::FocusRange,
@@ -107,8 +111,11 @@ impl Layer for TerrainMap {
     }
 }
 
-fn gradient_to_flatness(gradient: f64) -> Option<f64> {
-    let flatness = 1.0 - gradient.abs() / 5.0;
+/// Slope magnitude, in rise per unit distance, beyond which terrain is not flat at all.
+const STEEPEST_FLAT_SLOPE: f64 = 5.0;
+
+fn slope_to_flatness(slope: f64) -> Option<f64> {
+    let flatness = 1.0 - slope / STEEPEST_FLAT_SLOPE;
     if flatness < 0.0 {
         return None;
     }
@@ -120,11 +127,16 @@ fn main() {
     let terrain_path = format!("./data/in/{}.particlemap", particlemap_id);
     let terrain_map = TerrainMap::new(&terrain_path, 0.0025);
     let drainage_path = format!("./data/out/drainage-{}.particlemap", particlemap_id);
-    let drainage_map = DrainageMap::new(&terrain_map.particle_map, 1.0, 0.01);
+    let drainage_map = DrainageMap::new(&terrain_map.particle_map, 1.0, 0.01, BoundaryPolicy::Open)
+        .expect("Error building drainage map");
     drainage_map.save_to_file(&drainage_path);
     let drainage_map = DrainageMap::load_from_file(&drainage_path, 1.0, 0.01).unwrap();
-    //elevation_map, minimum_neighbor_num, sea_level, gradient_to_flatness
-    let flatness_map = FlatnessMap::new(&terrain_map.particle_map, 1, 1e-3, gradient_to_flatness);
+    let flatness_map = FlatnessMapBuilder::new(&terrain_map.particle_map)
+        .minimum_neighbor_num(1)
+        .sea_level(1e-3)
+        .boundary_policy(BoundaryPolicy::Closed)
+        .metric(FlatnessMetric::SlopeMagnitude)
+        .build(slope_to_flatness);
     let flatness_path = format!("./data/out/flatness-{}.particlemap", particlemap_id);
     flatness_map.save_to_file(&flatness_path);
     let flatness_map = FlatnessMap::load_from_file(&flatness_path).unwrap();
@@ -0,0 +1,207 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use worley_particle::map::{rw::ParticleMapAttributeRW, ParticleMap};
+
+use crate::{
+    boundary::{Boundary, BoundaryPolicy},
+    rw::write_sorted_to_file,
+    slope::estimate_slope,
+    voronoi::VoronoiCache,
+};
+
+/// Direction a particle faces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aspect {
+    /// Steepest way down, in radians counterclockwise from the x axis. Zero on flat
+    /// terrain.
+    pub angle: f64,
+    /// Rise per unit distance along the steepest way down.
+    pub slope: f64,
+}
+
+impl Aspect {
+    /// How directly the surface faces the sun, from 0 (in its own shadow) to 1 (facing
+    /// it). `sun_azimuth` is counterclockwise from the x axis and `sun_altitude` above the
+    /// horizon, both in radians.
+    pub fn sun_facing_index(&self, sun_azimuth: f64, sun_altitude: f64) -> f64 {
+        let inclination = self.slope.atan();
+        let index = sun_altitude.sin() * inclination.cos()
+            + sun_altitude.cos() * inclination.sin() * (sun_azimuth - self.angle).cos();
+        index.clamp(0.0, 1.0)
+    }
+}
+
+impl ParticleMapAttributeRW for Aspect {
+    fn from_strs(s: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Aspect {
+            angle: s[0].parse::<f64>()?,
+            slope: s[1].parse::<f64>()?,
+        })
+    }
+
+    fn to_strings(&self) -> Vec<String> {
+        vec![self.angle.to_string(), self.slope.to_string()]
+    }
+
+    fn len_strs() -> usize {
+        2
+    }
+}
+
+pub struct AspectMap {
+    pub particle_map: ParticleMap<Aspect>,
+    voronoi: VoronoiCache,
+}
+
+impl AspectMap {
+    pub fn new(elevation_map: &ParticleMap<f64>, boundary_policy: BoundaryPolicy) -> Self {
+        Self::new_with_voronoi(
+            elevation_map,
+            &VoronoiCache::new(elevation_map),
+            boundary_policy,
+        )
+    }
+
+    pub fn new_with_voronoi(
+        elevation_map: &ParticleMap<f64>,
+        voronoi: &VoronoiCache,
+        boundary_policy: BoundaryPolicy,
+    ) -> Self {
+        let boundary = Boundary::new(elevation_map, boundary_policy);
+        let particles = elevation_map
+            .iter()
+            .map(|(particle, _)| *particle)
+            .collect::<Vec<_>>();
+
+        let particles_iter = par_iter!(particles);
+
+        let particle_map = particles_iter
+            .filter_map(|particle| {
+                let slope = estimate_slope(elevation_map, voronoi, &boundary, particle)?;
+                Some((
+                    *particle,
+                    Aspect {
+                        angle: slope.aspect().unwrap_or(0.0),
+                        slope: slope.magnitude(),
+                    },
+                ))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect::<ParticleMap<Aspect>>();
+
+        Self {
+            particle_map,
+            voronoi: voronoi.clone(),
+        }
+    }
+
+    pub fn save_to_file(&self, file_path: &str) {
+        write_sorted_to_file(&self.particle_map, file_path).expect("Error writing aspect map");
+    }
+
+    pub fn load_from_file(file_path: &str) -> Option<Self> {
+        let particle_map = ParticleMap::<Aspect>::read_from_file(file_path).ok()?;
+        Some(Self {
+            particle_map,
+            voronoi: VoronoiCache::default(),
+        })
+    }
+
+    pub fn map(&self) -> &ParticleMap<Aspect> {
+        &self.particle_map
+    }
+
+    pub fn voronoi(&self) -> &VoronoiCache {
+        &self.voronoi
+    }
+
+    pub fn sun_facing_map(&self, sun_azimuth: f64, sun_altitude: f64) -> ParticleMap<f64> {
+        self.particle_map
+            .iter()
+            .map(|(particle, aspect)| {
+                (
+                    *particle,
+                    aspect.sun_facing_index(sun_azimuth, sun_altitude),
+                )
+            })
+            .collect::<ParticleMap<f64>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_4;
+
+    use super::*;
+    use crate::test_util::terrain;
+
+    #[test]
+    fn aspect_faces_downhill() {
+        let scale = terrain(1.0, 1.0, |_, _| 0.0).params().scale;
+        // Rising towards +x, so facing -x.
+        let elevation_map = terrain(5.0, 5.0, |x, _| 0.5 * x * scale);
+        let aspect_map = AspectMap::new(&elevation_map, BoundaryPolicy::Closed);
+        for (_, aspect) in aspect_map.map().iter() {
+            assert!((aspect.angle.abs() - std::f64::consts::PI).abs() < 1e-9);
+            assert!((aspect.slope - 0.5).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn slopes_facing_the_sun_are_lit_more() {
+        let flat = Aspect {
+            angle: 0.0,
+            slope: 0.0,
+        };
+        assert!((flat.sun_facing_index(1.0, FRAC_PI_4) - FRAC_PI_4.sin()).abs() < 1e-12);
+
+        let facing = Aspect {
+            angle: 1.0,
+            slope: 0.5,
+        };
+        let away = Aspect {
+            angle: 1.0 + std::f64::consts::PI,
+            ..facing
+        };
+        let (facing, away) = (
+            facing.sun_facing_index(1.0, FRAC_PI_4),
+            away.sun_facing_index(1.0, FRAC_PI_4),
+        );
+        assert!(facing > FRAC_PI_4.sin() && away < FRAC_PI_4.sin());
+
+        // A steep slope facing away from a low sun is in its own shadow.
+        let cliff = Aspect {
+            angle: 0.0,
+            slope: 10.0,
+        };
+        assert_eq!(cliff.sun_facing_index(std::f64::consts::PI, 0.1), 0.0);
+    }
+}
+
+#[cfg(feature = "visualize")]
+mod visualization {
+    use gtk4::{cairo::Context, DrawingArea};
+    use vislayers::{geometry::FocusRange, window::Layer};
+
+    use super::AspectMap;
+
+    impl Layer for AspectMap {
+        fn draw(&self, drawing_area: &DrawingArea, ctx: &Context, focus_range: &FocusRange) {
+            self.voronoi.fill_cells(
+                drawing_area,
+                ctx,
+                focus_range,
+                self.particle_map.iter(),
+                |aspect| {
+                    [
+                        0.5 + 0.5 * aspect.angle.cos(),
+                        0.5 + 0.5 * aspect.angle.sin(),
+                        0.5,
+                        aspect.slope / (1.0 + aspect.slope),
+                    ]
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use worley_particle::{map::ParticleMap, Particle};
+
+/// How particles on the edge of a map treat their neighbors outside of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundaryPolicy {
+    /// The edge is open: water reaching an edge particle leaves the map there.
+    Open,
+    /// The edge is a wall: missing neighbors are ignored.
+    #[default]
+    Closed,
+    /// Opposite edges are joined, so missing neighbors wrap around the map.
+    Periodic,
+}
+
+pub(crate) struct Boundary<'a, T> {
+    map: &'a ParticleMap<T>,
+    wrapping: Wrapping,
+}
+
+/// How sites are seen across the periodic edges of a map, kept apart from the map itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Wrapping {
+    policy: BoundaryPolicy,
+    min: (f64, f64),
+    period: (f64, f64),
+}
+
+pub(crate) struct Neighbors {
+    /// Neighbors inside the map with the site they are seen at from the particle, which
+    /// differs from their own site when they were reached across a periodic edge.
+    pub inside: Vec<(Particle, (f64, f64))>,
+    /// Number of neighbors that are outside the map and could not be resolved.
+    pub outside: usize,
+}
+
+impl<'a, T> Boundary<'a, T> {
+    pub fn new(map: &'a ParticleMap<T>, policy: BoundaryPolicy) -> Self {
+        let (mut min, mut max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+        if policy == BoundaryPolicy::Periodic {
+            for (particle, _) in map.iter() {
+                let site = particle.site();
+                min = (min.0.min(site.0), min.1.min(site.1));
+                max = (max.0.max(site.0), max.1.max(site.1));
+            }
+        }
+        let scale = map.params().scale;
+        Self {
+            map,
+            wrapping: Wrapping {
+                policy,
+                min,
+                period: (max.0 - min.0 + scale, max.1 - min.1 + scale),
+            },
+        }
+    }
+
+    pub fn wrapping(&self) -> Wrapping {
+        self.wrapping
+    }
+
+    pub fn resolve(&self, particle: &Particle, neighbors: &[Particle]) -> Neighbors {
+        let mut inside = Vec::with_capacity(neighbors.len());
+        let mut outside = 0;
+        for neighbor in neighbors {
+            if self.map.get(neighbor).is_some() {
+                inside.push((*neighbor, neighbor.site()));
+                continue;
+            }
+            match self.wrap(particle, neighbor) {
+                Some(wrapped) => inside.push((wrapped, neighbor.site())),
+                None => outside += 1,
+            }
+        }
+        Neighbors { inside, outside }
+    }
+
+    /// Site of `target` as seen from `from`, taking the shortest way across periodic edges.
+    pub fn unwrap_site(&self, from: (f64, f64), target: (f64, f64)) -> (f64, f64) {
+        self.wrapping.unwrap_site(from, target)
+    }
+
+    fn wrap(&self, particle: &Particle, neighbor: &Particle) -> Option<Particle> {
+        let wrapped_site = self.wrapping.wrap_site(neighbor.site())?;
+        let params = *self.map.params();
+        Particle::from_inside_radius(wrapped_site.0, wrapped_site.1, params, params.scale)
+            .into_iter()
+            .filter(|candidate| candidate != particle && self.map.get(candidate).is_some())
+            .min_by(|a, b| {
+                let distance = |p: &Particle| {
+                    let s = p.site();
+                    (s.0 - wrapped_site.0).hypot(s.1 - wrapped_site.1)
+                };
+                distance(a).total_cmp(&distance(b))
+            })
+    }
+}
+
+impl Wrapping {
+    /// Site of `target` as seen from `from`, taking the shortest way across periodic edges.
+    pub fn unwrap_site(&self, from: (f64, f64), target: (f64, f64)) -> (f64, f64) {
+        if self.policy != BoundaryPolicy::Periodic {
+            return target;
+        }
+        let unwrap = |from: f64, target: f64, period: f64| {
+            target - ((target - from) / period).round() * period
+        };
+        (
+            unwrap(from.0, target.0, self.period.0),
+            unwrap(from.1, target.1, self.period.1),
+        )
+    }
+
+    /// Site inside the map that `site` wraps around to, if the edges are periodic.
+    fn wrap_site(&self, site: (f64, f64)) -> Option<(f64, f64)> {
+        if self.policy != BoundaryPolicy::Periodic {
+            return None;
+        }
+        let wrap = |value: f64, min: f64, period: f64| min + (value - min).rem_euclid(period);
+        Some((
+            wrap(site.0, self.min.0, self.period.0),
+            wrap(site.1, self.min.1, self.period.1),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::terrain, voronoi::VoronoiCache};
+
+    fn corner(map: &ParticleMap<f64>) -> Particle {
+        map.iter()
+            .map(|(particle, _)| *particle)
+            .min_by(|a, b| {
+                let (a, b) = (a.site(), b.site());
+                (a.0 + a.1).total_cmp(&(b.0 + b.1))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn closed_and_open_edges_leave_neighbors_outside() {
+        let map = terrain(4.0, 4.0, |_, _| 0.0);
+        let voronoi = VoronoiCache::new(&map);
+        let corner = corner(&map);
+        let cell_neighbors = &voronoi.get_or_compute(&corner).neighbors;
+        for policy in [BoundaryPolicy::Closed, BoundaryPolicy::Open] {
+            let neighbors = Boundary::new(&map, policy).resolve(&corner, cell_neighbors);
+            assert!(neighbors.outside > 0);
+            assert_eq!(
+                neighbors.inside.len() + neighbors.outside,
+                cell_neighbors.len()
+            );
+            for (neighbor, site) in neighbors.inside {
+                assert_eq!(site, neighbor.site());
+            }
+        }
+    }
+
+    #[test]
+    fn periodic_edges_wrap_neighbors_around() {
+        let map = terrain(4.0, 4.0, |_, _| 0.0);
+        let voronoi = VoronoiCache::new(&map);
+        let corner = corner(&map);
+        let cell_neighbors = &voronoi.get_or_compute(&corner).neighbors;
+        let boundary = Boundary::new(&map, BoundaryPolicy::Periodic);
+        let neighbors = boundary.resolve(&corner, cell_neighbors);
+
+        assert_eq!(neighbors.outside, 0);
+        assert_eq!(neighbors.inside.len(), cell_neighbors.len());
+        let corner_site = corner.site();
+        for (neighbor, site) in neighbors.inside {
+            assert!(map.get(&neighbor).is_some());
+            // Wrapped neighbors are seen next to the corner, across the edge.
+            let unwrapped = boundary.unwrap_site(corner_site, neighbor.site());
+            let scale = map.params().scale;
+            assert!((unwrapped.0 - corner_site.0).abs() < 2.0 * scale);
+            assert!((unwrapped.1 - corner_site.1).abs() < 2.0 * scale);
+            assert!((unwrapped.0 - site.0).abs() < scale);
+            assert!((unwrapped.1 - site.1).abs() < scale);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::{HashMap, VecDeque};
+
+use worley_particle::{
+    map::{lerp::InterpolationMethod, IDWStrategy, ParticleMap},
+    Particle,
+};
+
+use crate::{
+    geometry::{closest_on_segment, segments, signed_area},
+    sea::SeaMask,
+    voronoi::VoronoiCache,
+};
+
+/// Body of water on the other side of a coastline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaterBody {
+    /// Water connected to the edge of the map.
+    Sea,
+    /// Water enclosed by land.
+    Lake,
+    /// No water particle was found next to the ring, as for a ring running only along the
+    /// edge of the map.
+    Unknown,
+}
+
+/// Closed ring along the sea level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coastline {
+    pub points: Vec<(f64, f64)>,
+    pub water: WaterBody,
+    /// Whether the ring is the outline of a landmass rather than the shore of a lake or a
+    /// sea enclosed by it.
+    pub encloses_land: bool,
+}
+
+impl Coastline {
+    pub fn length(&self) -> f64 {
+        segments(&self.points)
+            .map(|(a, b)| (a.0 - b.0).hypot(a.1 - b.1))
+            .sum()
+    }
+
+    /// A landmass surrounded by the sea.
+    pub fn is_island(&self) -> bool {
+        self.encloses_land && self.water == WaterBody::Sea
+    }
+
+    pub fn distance_to(&self, x: f64, y: f64) -> f64 {
+        segments(&self.points)
+            .map(|(a, b)| {
+                let closest = closest_on_segment((x, y), a, b);
+                (closest.0 - x).hypot(closest.1 - y)
+            })
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+pub struct CoastlineMap {
+    coastlines: Vec<Coastline>,
+    sea_mask: SeaMask,
+}
+
+impl CoastlineMap {
+    /// `resolution` is passed on to `ParticleMap::isobands`.
+    pub fn new(
+        elevation_map: &ParticleMap<f64>,
+        sea_level: f64,
+        resolution: f64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_voronoi(
+            elevation_map,
+            &VoronoiCache::new(elevation_map),
+            sea_level,
+            resolution,
+        )
+    }
+
+    pub fn new_with_voronoi(
+        elevation_map: &ParticleMap<f64>,
+        voronoi: &VoronoiCache,
+        sea_level: f64,
+        resolution: f64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let sea_mask = SeaMask::new(elevation_map, sea_level);
+        let bands = elevation_map.isobands(
+            elevation_map.corners(),
+            resolution,
+            &[sea_level],
+            InterpolationMethod::IDW(IDWStrategy::default_from_params(elevation_map.params())),
+            true,
+        )?;
+
+        // Isoband vertices are offset by half a particle from the sites, as in
+        // `examples/terrain.rs`.
+        let offset = elevation_map.params().scale / 2.0;
+        let water_bodies = classify_water(elevation_map, voronoi, &sea_mask);
+        let coastlines = bands
+            .into_iter()
+            .flat_map(|band| band.polygons)
+            .map(|polygon| {
+                polygon
+                    .into_iter()
+                    .map(|point| (point.0 - offset, point.1 - offset))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|ring| ring.len() >= 3)
+            .map(|points| {
+                let (encloses_land, water) =
+                    classify_sides(elevation_map, &sea_mask, &water_bodies, &points);
+                Coastline {
+                    points,
+                    water,
+                    encloses_land,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            coastlines,
+            sea_mask,
+        })
+    }
+
+    pub fn coastlines(&self) -> &[Coastline] {
+        &self.coastlines
+    }
+
+    pub fn islands(&self) -> impl Iterator<Item = &Coastline> {
+        self.coastlines
+            .iter()
+            .filter(|coastline| coastline.is_island())
+    }
+
+    pub fn lakes(&self) -> impl Iterator<Item = &Coastline> {
+        self.coastlines
+            .iter()
+            .filter(|coastline| coastline.water == WaterBody::Lake)
+    }
+
+    pub fn sea_mask(&self) -> &SeaMask {
+        &self.sea_mask
+    }
+
+    /// Distance from the point to the closest coastline, or `None` if there is no coast.
+    pub fn distance_to_coast(&self, x: f64, y: f64) -> Option<f64> {
+        self.coastlines
+            .iter()
+            .map(|coastline| coastline.distance_to(x, y))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+/// Splits the sea particles into connected bodies of water, those touching the edge of the
+/// map being the sea and the others lakes.
+fn classify_water(
+    elevation_map: &ParticleMap<f64>,
+    voronoi: &VoronoiCache,
+    sea_mask: &SeaMask,
+) -> HashMap<Particle, WaterBody> {
+    let mut water_bodies = HashMap::new();
+    for start in sea_mask.iter() {
+        if water_bodies.contains_key(start) {
+            continue;
+        }
+        let mut members = vec![*start];
+        let mut queue = VecDeque::from([*start]);
+        let mut touches_edge = false;
+        water_bodies.insert(*start, WaterBody::Lake);
+        while let Some(current) = queue.pop_front() {
+            for neighbor in voronoi.get_or_compute(&current).neighbors.iter() {
+                if elevation_map.get(neighbor).is_none() {
+                    touches_edge = true;
+                } else if sea_mask.is_sea(neighbor) && !water_bodies.contains_key(neighbor) {
+                    water_bodies.insert(*neighbor, WaterBody::Lake);
+                    members.push(*neighbor);
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+        if touches_edge {
+            for member in members {
+                water_bodies.insert(member, WaterBody::Sea);
+            }
+        }
+    }
+    water_bodies
+}
+
+/// Whether the ring has land on its inner side, and the body of water along it, found by
+/// looking at the particles on either side of each of its segments.
+fn classify_sides(
+    elevation_map: &ParticleMap<f64>,
+    sea_mask: &SeaMask,
+    water_bodies: &HashMap<Particle, WaterBody>,
+    ring: &[(f64, f64)],
+) -> (bool, WaterBody) {
+    let orientation = signed_area(ring).signum();
+    let step = elevation_map.params().scale / 4.0;
+    let (mut land_inside, mut sea_inside) = (0, 0);
+    let (mut sea, mut lake) = (0, 0);
+    for (a, b) in segments(ring) {
+        let length = (b.0 - a.0).hypot(b.1 - a.1);
+        if length == 0.0 {
+            continue;
+        }
+        // Points a little to either side of the middle of the segment, the inner one first.
+        let normal = (
+            -(b.1 - a.1) / length * orientation * step,
+            (b.0 - a.0) / length * orientation * step,
+        );
+        let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+        let inner = nearest_particle(elevation_map, (middle.0 + normal.0, middle.1 + normal.1));
+        let outer = nearest_particle(elevation_map, (middle.0 - normal.0, middle.1 - normal.1));
+
+        match inner.map(|particle| sea_mask.is_sea(&particle)) {
+            Some(true) => sea_inside += 1,
+            Some(false) => land_inside += 1,
+            None => {}
+        }
+        let water = [inner, outer]
+            .into_iter()
+            .flatten()
+            .find_map(|particle| water_bodies.get(&particle));
+        match water {
+            Some(WaterBody::Sea) => sea += 1,
+            Some(WaterBody::Lake) => lake += 1,
+            _ => {}
+        }
+    }
+
+    let water = match (sea, lake) {
+        (0, 0) => WaterBody::Unknown,
+        (sea, lake) if sea >= lake => WaterBody::Sea,
+        _ => WaterBody::Lake,
+    };
+    (land_inside >= sea_inside, water)
+}
+
+fn nearest_particle(elevation_map: &ParticleMap<f64>, point: (f64, f64)) -> Option<Particle> {
+    let params = *elevation_map.params();
+    Particle::from_inside_radius(point.0, point.1, params, params.scale * 2.0)
+        .into_iter()
+        .filter(|particle| elevation_map.get(particle).is_some())
+        .min_by(|a, b| {
+            let distance = |particle: &Particle| {
+                let site = particle.site();
+                (site.0 - point.0).hypot(site.1 - point.1)
+            };
+            distance(a).total_cmp(&distance(b))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::terrain;
+
+    /// Island over [3, 9) x [3, 9) in a 12 x 12 sea, with a lake over [5, 7) x [5, 7).
+    fn island_with_lake() -> ParticleMap<f64> {
+        terrain(12.0, 12.0, |x, y| {
+            let within = |low: f64, high: f64| (low..high).contains(&x) && (low..high).contains(&y);
+            if within(5.0, 7.0) || !within(3.0, 9.0) {
+                -1.0
+            } else {
+                1.0
+            }
+        })
+    }
+
+    #[test]
+    fn islands_and_lakes_are_told_apart() {
+        let elevation_map = island_with_lake();
+        let scale = elevation_map.params().scale;
+        let coastline_map = CoastlineMap::new(&elevation_map, 0.0, 1.0).unwrap();
+
+        assert_eq!(coastline_map.coastlines().len(), 2);
+        let islands = coastline_map.islands().collect::<Vec<_>>();
+        assert_eq!(islands.len(), 1);
+        assert!((islands[0].length() - 24.0 * scale).abs() < 1e-9);
+        assert!((signed_area(&islands[0].points).abs() - 36.0 * scale * scale).abs() < 1e-9);
+
+        let lakes = coastline_map.lakes().collect::<Vec<_>>();
+        assert_eq!(lakes.len(), 1);
+        assert!(!lakes[0].encloses_land);
+        assert!(!lakes[0].is_island());
+        assert!((lakes[0].length() - 8.0 * scale).abs() < 1e-9);
+
+        assert_eq!(coastline_map.sea_mask().len(), 144 - 36 + 4);
+    }
+
+    #[test]
+    fn distances_are_measured_to_the_nearest_coastline() {
+        let elevation_map = island_with_lake();
+        let scale = elevation_map.params().scale;
+        let coastline_map = CoastlineMap::new(&elevation_map, 0.0, 1.0).unwrap();
+        let distance = |x: f64, y: f64| {
+            coastline_map
+                .distance_to_coast(x * scale, y * scale)
+                .unwrap()
+                / scale
+        };
+
+        assert!((distance(3.5, 4.0) - 0.5).abs() < 1e-9);
+        assert!((distance(1.0, 1.0) - 2.0_f64.hypot(2.0)).abs() < 1e-9);
+        // In the middle of the lake, and on land between the lake and the sea.
+        assert!((distance(6.0, 6.0) - 1.0).abs() < 1e-9);
+        assert!((distance(4.0, 6.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rings_without_water_along_them_are_unknown() {
+        let land = terrain(6.0, 6.0, |_, _| 1.0);
+        let coastline_map = CoastlineMap::new(&land, 0.0, 1.0).unwrap();
+        assert!(coastline_map.sea_mask().is_empty());
+        // A single ring along the edge of the map.
+        assert_eq!(coastline_map.coastlines().len(), 1);
+        for coastline in coastline_map.coastlines() {
+            assert_eq!(coastline.water, WaterBody::Unknown);
+            assert!(!coastline.is_island());
+        }
+        assert_eq!(coastline_map.lakes().count(), 0);
+
+        let sea = terrain(6.0, 6.0, |_, _| -1.0);
+        let coastline_map = CoastlineMap::new(&sea, 0.0, 1.0).unwrap();
+        assert!(coastline_map.coastlines().is_empty());
+        assert_eq!(coastline_map.distance_to_coast(0.0, 0.0), None);
+    }
+}
+
+#[cfg(feature = "visualize")]
+mod visualization {
+    use gtk4::{cairo::Context, prelude::WidgetExt, DrawingArea};
+    use vislayers::{geometry::FocusRange, window::Layer};
+
+    use super::{CoastlineMap, WaterBody};
+
+    impl Layer for CoastlineMap {
+        fn draw(&self, drawing_area: &DrawingArea, ctx: &Context, focus_range: &FocusRange) {
+            let area_width = drawing_area.width();
+            let area_height = drawing_area.height();
+
+            let rect = focus_range.to_rect(area_width as f64, area_height as f64);
+
+            for coastline in self.coastlines.iter() {
+                let color = match coastline.water {
+                    WaterBody::Sea => [0.1, 0.2, 0.6],
+                    WaterBody::Lake => [0.2, 0.6, 0.8],
+                    WaterBody::Unknown => [0.5, 0.5, 0.5],
+                };
+                ctx.set_source_rgb(color[0], color[1], color[2]);
+                ctx.set_line_width(1.5);
+
+                ctx.new_path();
+                for (i, point) in coastline.points.iter().enumerate() {
+                    let x = rect.map_coord_x(point.0, 0.0, area_width as f64);
+                    let y = rect.map_coord_y(point.1, 0.0, area_height as f64);
+
+                    if i == 0 {
+                        ctx.move_to(x, y);
+                    } else {
+                        ctx.line_to(x, y);
+                    }
+                }
+                ctx.close_path();
+
+                ctx.stroke().expect("Failed to draw coastline");
+            }
+        }
+    }
+}
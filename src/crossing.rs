@@ -0,0 +1,421 @@
+use std::collections::{HashMap, HashSet};
+
+use worley_particle::Particle;
+
+use crate::{
+    drainage::{
+        map::{compare_sites, DrainageMap},
+        node::DrainageBasinNode,
+    },
+    road::Road,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrossingParams {
+    /// Deepest water a ford can have.
+    pub maximum_ford_depth: f64,
+    /// Fastest water a ford can have.
+    pub maximum_ford_velocity: f64,
+}
+
+impl Default for CrossingParams {
+    fn default() -> Self {
+        Self {
+            maximum_ford_depth: 0.5,
+            maximum_ford_velocity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossingKind {
+    Bridge,
+    Ford,
+}
+
+/// Where and how a river can be crossed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crossing {
+    pub kind: CrossingKind,
+    /// The river node the crossing is on.
+    pub particle: Particle,
+    /// Point in the middle of the river reach.
+    pub site: (f64, f64),
+    pub river_width: f64,
+    pub depth: f64,
+    pub velocity: f64,
+    pub drainage_area: f64,
+    /// Elevation of the left and right banks, looking downstream. Falls back to the river
+    /// elevation where a bank has no neighbor.
+    pub bank_elevations: (f64, f64),
+}
+
+/// Describes a crossing over the river at `particle`, or `None` if it carries no river.
+pub fn crossing_at(
+    drainage_map: &DrainageMap,
+    particle: &Particle,
+    params: &CrossingParams,
+) -> Option<Crossing> {
+    let node = drainage_map.map().get(particle)?;
+    if !drainage_map.is_river(node) {
+        return None;
+    }
+    let hydraulics = drainage_map.hydraulics(particle)?;
+    let kind = if hydraulics.is_fordable(params.maximum_ford_depth, params.maximum_ford_velocity) {
+        CrossingKind::Ford
+    } else {
+        CrossingKind::Bridge
+    };
+
+    Some(Crossing {
+        kind,
+        particle: *particle,
+        site: node.main_river.evaluate(0.5),
+        river_width: hydraulics.width,
+        depth: hydraulics.depth,
+        velocity: hydraulics.velocity,
+        drainage_area: node.drainage_area,
+        bank_elevations: bank_elevations(drainage_map, node),
+    })
+}
+
+fn bank_elevations(drainage_map: &DrainageMap, node: &DrainageBasinNode) -> (f64, f64) {
+    let site = node.particle.site();
+    let direction = node.direction();
+    let (dx, dy) = (direction.cos(), direction.sin());
+
+    // The neighbor closest to perpendicular on each side of the flow.
+    let mut left: Option<(f64, f64)> = None;
+    let mut right: Option<(f64, f64)> = None;
+    for neighbor in drainage_map
+        .voronoi()
+        .get_or_compute(&node.particle)
+        .neighbors
+        .iter()
+    {
+        if *neighbor == node.flow_to {
+            continue;
+        }
+        let Some(neighbor_node) = drainage_map.map().get(neighbor) else {
+            continue;
+        };
+        if neighbor_node.flow_to == node.particle {
+            continue;
+        }
+        let neighbor_site = neighbor.site();
+        let (nx, ny) = (neighbor_site.0 - site.0, neighbor_site.1 - site.1);
+        let length = nx.hypot(ny);
+        if length == 0.0 {
+            continue;
+        }
+        let cross = (dx * ny - dy * nx) / length;
+        let side = if cross > 0.0 { &mut left } else { &mut right };
+        if side.is_none_or(|(best, _)| cross.abs() > best) {
+            *side = Some((cross.abs(), neighbor_node.elevation));
+        }
+    }
+
+    (
+        left.map_or(node.elevation, |(_, elevation)| elevation),
+        right.map_or(node.elevation, |(_, elevation)| elevation),
+    )
+}
+
+/// One crossing for every river reach, a reach running from a source or a confluence
+/// down to the next confluence or outlet. Confluences are never crossed, so a reach made
+/// of a single confluence gets no crossing.
+///
+/// A reach is forded at its shallowest fordable node, slower water breaking ties. Only a
+/// reach without fordable nodes is bridged, at its narrowest node, shallower water
+/// breaking ties.
+pub fn detect_crossings(drainage_map: &DrainageMap, params: &CrossingParams) -> Vec<Crossing> {
+    let rivers = drainage_map
+        .map()
+        .iter()
+        .filter(|(_, node)| drainage_map.is_river(node))
+        .map(|(particle, node)| (*particle, node))
+        .collect::<HashMap<Particle, &DrainageBasinNode>>();
+    let mut river_inflows: HashMap<Particle, usize> = HashMap::new();
+    for node in rivers.values() {
+        if !node.is_outlet() && rivers.contains_key(&node.flow_to) {
+            *river_inflows.entry(node.flow_to).or_default() += 1;
+        }
+    }
+    let inflow_count = |particle: &Particle| river_inflows.get(particle).copied().unwrap_or(0);
+    let is_head = |particle: &Particle| inflow_count(particle) != 1;
+
+    let mut crossings = Vec::new();
+    let mut visited = HashSet::new();
+    let mut heads = rivers
+        .keys()
+        .filter(|particle| is_head(particle))
+        .copied()
+        .collect::<Vec<_>>();
+    heads.sort_by(compare_sites);
+
+    for head in heads {
+        let mut ford: Option<Crossing> = None;
+        let mut bridge: Option<Crossing> = None;
+        let mut current = head;
+        while visited.insert(current) {
+            let crossing = (inflow_count(&current) < 2)
+                .then(|| crossing_at(drainage_map, &current, params))
+                .flatten();
+            match crossing {
+                Some(crossing) if crossing.kind == CrossingKind::Ford => {
+                    let shallower = ford.is_none_or(|ford| {
+                        crossing
+                            .depth
+                            .total_cmp(&ford.depth)
+                            .then(crossing.velocity.total_cmp(&ford.velocity))
+                            .is_lt()
+                    });
+                    if shallower {
+                        ford = Some(crossing);
+                    }
+                }
+                Some(crossing) => {
+                    let narrower = bridge.is_none_or(|bridge| {
+                        crossing
+                            .river_width
+                            .total_cmp(&bridge.river_width)
+                            .then(crossing.depth.total_cmp(&bridge.depth))
+                            .is_lt()
+                    });
+                    if narrower {
+                        bridge = Some(crossing);
+                    }
+                }
+                None => {}
+            }
+            let flow_to = rivers[&current].flow_to;
+            if flow_to == current || !rivers.contains_key(&flow_to) || is_head(&flow_to) {
+                break;
+            }
+            current = flow_to;
+        }
+        crossings.extend(ford.or(bridge));
+    }
+
+    crossings
+}
+
+/// Crossings for every bridge of the roads.
+pub fn road_crossings(
+    drainage_map: &DrainageMap,
+    roads: &[Road],
+    params: &CrossingParams,
+) -> Vec<Crossing> {
+    roads
+        .iter()
+        .flat_map(|road| road.bridges.iter())
+        .filter_map(|bridge| {
+            let mut crossing = crossing_at(drainage_map, &bridge.river, params)?;
+            crossing.site = bridge.site;
+            Some(crossing)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drainage::{builder::DrainageMapBuilder, width::CustomWidth},
+        road::Bridge,
+        test_util::{particle_at, terrain},
+    };
+
+    /// Plane falling towards the sea at x = 0 and, more gently, towards y = 0, so that
+    /// every row of particles drains along itself into the sea.
+    fn rows() -> DrainageMap {
+        let elevation_map = terrain(8.0, 5.0, |x, y| x * 0.1 + y * 0.01);
+        DrainageMapBuilder::new(&elevation_map)
+            .sea_level(0.1)
+            .river_ignoreable_width_strength(0.0)
+            .build()
+            .unwrap()
+    }
+
+    fn elevation_at(drainage_map: &DrainageMap, x: f64, y: f64) -> f64 {
+        let particle = particle_at(drainage_map.map(), x, y);
+        drainage_map.map().get(&particle).unwrap().elevation
+    }
+
+    #[test]
+    fn crossings_describe_the_river_and_its_banks() {
+        let drainage_map = rows();
+        let particle = particle_at(drainage_map.map(), 3.5, 2.5);
+        let node = drainage_map.map().get(&particle).unwrap();
+        let hydraulics = drainage_map.hydraulics(&particle).unwrap();
+
+        let crossing = crossing_at(&drainage_map, &particle, &CrossingParams::default()).unwrap();
+        assert_eq!(crossing.particle, particle);
+        assert_eq!(crossing.site, node.main_river.evaluate(0.5));
+        assert_eq!(crossing.river_width, hydraulics.width);
+        assert_eq!(crossing.depth, hydraulics.depth);
+        assert_eq!(crossing.drainage_area, node.drainage_area);
+        // Flowing towards -x, the left bank is on the -y side.
+        assert_eq!(
+            crossing.bank_elevations,
+            (
+                elevation_at(&drainage_map, 3.5, 1.5),
+                elevation_at(&drainage_map, 3.5, 3.5)
+            )
+        );
+
+        let edge = particle_at(drainage_map.map(), 3.5, 0.5);
+        let crossing = crossing_at(&drainage_map, &edge, &CrossingParams::default()).unwrap();
+        assert_eq!(
+            crossing.bank_elevations,
+            (
+                elevation_at(&drainage_map, 3.5, 0.5),
+                elevation_at(&drainage_map, 3.5, 1.5)
+            )
+        );
+
+        let fords = CrossingParams {
+            maximum_ford_depth: f64::MAX,
+            maximum_ford_velocity: f64::MAX,
+        };
+        let bridges = CrossingParams {
+            maximum_ford_depth: 0.0,
+            maximum_ford_velocity: 0.0,
+        };
+        assert_eq!(
+            crossing_at(&drainage_map, &particle, &fords).unwrap().kind,
+            CrossingKind::Ford
+        );
+        assert_eq!(
+            crossing_at(&drainage_map, &particle, &bridges)
+                .unwrap()
+                .kind,
+            CrossingKind::Bridge
+        );
+    }
+
+    #[test]
+    fn dry_particles_have_no_crossing() {
+        let elevation_map = terrain(8.0, 5.0, |x, y| x * 0.1 + y * 0.01);
+        let drainage_map = DrainageMapBuilder::new(&elevation_map)
+            .river_ignoreable_width_strength(f64::MAX)
+            .build()
+            .unwrap();
+        let particle = particle_at(&elevation_map, 3.5, 2.5);
+        assert_eq!(
+            crossing_at(&drainage_map, &particle, &CrossingParams::default()),
+            None
+        );
+        assert!(detect_crossings(&drainage_map, &CrossingParams::default()).is_empty());
+    }
+
+    /// Width 1 everywhere but narrow at x = 4.5 and wide at x = 5.5.
+    fn varying_width(node: &DrainageBasinNode) -> f64 {
+        let scale = node.particle.params().scale;
+        match (node.particle.site().0 / scale * 2.0).round() as i64 {
+            9 => 0.5,
+            11 => 10.0,
+            _ => 1.0,
+        }
+    }
+
+    #[test]
+    fn reaches_are_bridged_where_narrowest_and_forded_where_shallowest() {
+        let drainage_map = rows().with_river_width_model(CustomWidth(varying_width));
+        let scale = drainage_map.map().params().scale;
+        let crossed_at = |params: &CrossingParams, kind: CrossingKind| {
+            let crossings = detect_crossings(&drainage_map, params);
+            // One reach per row.
+            assert_eq!(crossings.len(), 5);
+            let mut sites = crossings
+                .iter()
+                .map(|crossing| {
+                    assert_eq!(crossing.kind, kind);
+                    let site = crossing.particle.site();
+                    (site.0 / scale, site.1 / scale)
+                })
+                .collect::<Vec<_>>();
+            sites.sort_by(|a, b| a.1.total_cmp(&b.1));
+            sites
+        };
+
+        let bridges = CrossingParams {
+            maximum_ford_depth: 0.0,
+            maximum_ford_velocity: 0.0,
+        };
+        assert_eq!(
+            crossed_at(&bridges, CrossingKind::Bridge),
+            [0.5, 1.5, 2.5, 3.5, 4.5].map(|y| (4.5, y))
+        );
+
+        // The wide channel at x = 5.5 is the shallowest despite draining more than the
+        // sources upstream.
+        let fords = CrossingParams {
+            maximum_ford_depth: f64::MAX,
+            maximum_ford_velocity: f64::MAX,
+        };
+        assert_eq!(
+            crossed_at(&fords, CrossingKind::Ford),
+            [0.5, 1.5, 2.5, 3.5, 4.5].map(|y| (5.5, y))
+        );
+    }
+
+    #[test]
+    fn confluences_are_not_crossed() {
+        // Both sides drain across into the middle row, which is all confluences.
+        let elevation_map = terrain(8.0, 5.0, |x, y| x * 0.05 + (y - 2.5).abs() * 0.1);
+        let scale = elevation_map.params().scale;
+        let drainage_map = DrainageMapBuilder::new(&elevation_map)
+            .river_ignoreable_width_strength(0.0)
+            .river_width_model(CustomWidth(move |node: &DrainageBasinNode| {
+                if node.particle.site().1 / scale == 2.5 {
+                    0.1
+                } else {
+                    1.0
+                }
+            }))
+            .build()
+            .unwrap();
+        let bridges = CrossingParams {
+            maximum_ford_depth: 0.0,
+            maximum_ford_velocity: 0.0,
+        };
+
+        let crossings = detect_crossings(&drainage_map, &bridges);
+        // One reach down each side of every column.
+        assert_eq!(crossings.len(), 16);
+        for crossing in crossings {
+            assert_ne!(crossing.particle.site().1 / scale, 2.5);
+        }
+    }
+
+    #[test]
+    fn road_crossings_sit_on_the_bridges() {
+        let drainage_map = rows();
+        let (from, to) = (
+            particle_at(drainage_map.map(), 3.5, 2.5),
+            particle_at(drainage_map.map(), 3.5, 3.5),
+        );
+        let bridge = Bridge {
+            from,
+            to,
+            site: (3.5 * drainage_map.map().params().scale, 3.0),
+            river: from,
+            river_width: 0.1,
+        };
+        let outside = Bridge {
+            river: Particle::from_inside_radius(-10.0, -10.0, *drainage_map.map().params(), 1.0)[0],
+            ..bridge
+        };
+        let road = Road {
+            path: vec![from, to],
+            bridges: vec![bridge, outside],
+            cost: 1.0,
+        };
+
+        let crossings = road_crossings(&drainage_map, &[road], &CrossingParams::default());
+        assert_eq!(crossings.len(), 1);
+        assert_eq!(crossings[0].particle, from);
+        assert_eq!(crossings[0].site, bridge.site);
+    }
+}
@@ -0,0 +1,60 @@
+use super::hydraulics::ChannelHydraulics;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BraidParams {
+    /// Only reaches flatter than this (absolute slope) are braided.
+    pub slope_threshold: f64,
+    /// Only reaches carrying at least this discharge are braided.
+    pub minimum_discharge: f64,
+    /// Number of sub-channels a braided reach splits into.
+    pub channels: usize,
+    /// Distance between neighbouring sub-channels relative to the river width.
+    pub spread: f64,
+}
+
+impl Default for BraidParams {
+    fn default() -> Self {
+        Self {
+            slope_threshold: 0.01,
+            minimum_discharge: 1.0,
+            channels: 3,
+            spread: 1.5,
+        }
+    }
+}
+
+impl BraidParams {
+    pub(crate) fn applies_to(&self, slope: f64, hydraulics: &ChannelHydraulics) -> bool {
+        self.channels > 1
+            && slope.abs() < self.slope_threshold
+            && hydraulics.discharge >= self.minimum_discharge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hydraulics(discharge: f64) -> ChannelHydraulics {
+        ChannelHydraulics {
+            discharge,
+            width: 1.0,
+            depth: 1.0,
+            velocity: 1.0,
+        }
+    }
+
+    #[test]
+    fn only_flat_reaches_with_enough_discharge_braid() {
+        let params = BraidParams::default();
+        assert!(params.applies_to(-0.005, &hydraulics(1.0)));
+        assert!(!params.applies_to(0.02, &hydraulics(1.0)));
+        assert!(!params.applies_to(0.005, &hydraulics(0.5)));
+
+        let single = BraidParams {
+            channels: 1,
+            ..params
+        };
+        assert!(!single.applies_to(0.0, &hydraulics(10.0)));
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{boundary::BoundaryPolicy, voronoi::VoronoiCache};
+
+use super::{
+    depression::{fill_depressions, DepressionHandling},
+    error::DrainageError,
+    hydraulics::HydraulicModel,
+    map::{build_drainage_basin, elevation_to_input, index_inflows, runoff, DrainageMap},
+    routing::RoutingMode,
+    width::{PowerLawWidth, RiverWidthModel},
+};
+
+/// Configures and builds a [`DrainageMap`]. Unless set, the river strength is 1.0, the
+/// ignoreable width strength 0.01, and the other options take their `Default`. Without a
+/// precipitation map every particle contributes its whole area.
+pub struct DrainageMapBuilder<'a> {
+    elevation_map: &'a ParticleMap<f64>,
+    voronoi: Option<VoronoiCache>,
+    river_strength: f64,
+    river_ignoreable_width_strength: f64,
+    sea_level: Option<f64>,
+    routing_mode: RoutingMode,
+    depression_handling: DepressionHandling,
+    precipitation_map: Option<&'a ParticleMap<f64>>,
+    boundary_policy: BoundaryPolicy,
+    river_width_model: Option<Box<dyn RiverWidthModel>>,
+    hydraulic_model: HydraulicModel,
+}
+
+impl<'a> DrainageMapBuilder<'a> {
+    pub fn new(elevation_map: &'a ParticleMap<f64>) -> Self {
+        Self {
+            elevation_map,
+            voronoi: None,
+            river_strength: 1.0,
+            river_ignoreable_width_strength: 0.01,
+            sea_level: None,
+            routing_mode: RoutingMode::default(),
+            depression_handling: DepressionHandling::default(),
+            precipitation_map: None,
+            boundary_policy: BoundaryPolicy::default(),
+            river_width_model: None,
+            hydraulic_model: HydraulicModel::default(),
+        }
+    }
+
+    /// Reuses the Voronoi cells of another builder instead of computing them.
+    pub fn voronoi(mut self, voronoi: &VoronoiCache) -> Self {
+        self.voronoi = Some(voronoi.clone());
+        self
+    }
+
+    /// Coefficient of the default square root river width model.
+    pub fn river_strength(mut self, river_strength: f64) -> Self {
+        self.river_strength = river_strength;
+        self
+    }
+
+    pub fn river_ignoreable_width_strength(mut self, strength: f64) -> Self {
+        self.river_ignoreable_width_strength = strength;
+        self
+    }
+
+    /// Particles below the sea level are outlets and don't drain anywhere.
+    pub fn sea_level(mut self, sea_level: f64) -> Self {
+        self.sea_level = Some(sea_level);
+        self
+    }
+
+    pub fn routing_mode(mut self, routing_mode: RoutingMode) -> Self {
+        self.routing_mode = routing_mode;
+        self
+    }
+
+    pub fn depression_handling(mut self, depression_handling: DepressionHandling) -> Self {
+        self.depression_handling = depression_handling;
+        self
+    }
+
+    /// Weights the area each particle contributes to the drainage area. Particles missing
+    /// from the map contribute nothing.
+    pub fn precipitation_map(mut self, precipitation_map: &'a ParticleMap<f64>) -> Self {
+        self.precipitation_map = Some(precipitation_map);
+        self
+    }
+
+    pub fn boundary_policy(mut self, boundary_policy: BoundaryPolicy) -> Self {
+        self.boundary_policy = boundary_policy;
+        self
+    }
+
+    /// Overrides the river width model set by [`river_strength`](Self::river_strength).
+    pub fn river_width_model(mut self, model: impl RiverWidthModel + 'static) -> Self {
+        self.river_width_model = Some(Box::new(model));
+        self
+    }
+
+    pub fn hydraulic_model(mut self, model: HydraulicModel) -> Self {
+        self.hydraulic_model = model;
+        self
+    }
+
+    pub fn build(self) -> Result<DrainageMap, DrainageError> {
+        let voronoi = self
+            .voronoi
+            .unwrap_or_else(|| VoronoiCache::new(self.elevation_map));
+
+        let terrain_map = elevation_to_input(self.elevation_map);
+        let terrain_map = match self.depression_handling {
+            DepressionHandling::Keep => terrain_map,
+            DepressionHandling::Fill { epsilon } => fill_depressions(
+                &terrain_map,
+                &voronoi,
+                self.boundary_policy,
+                self.sea_level,
+                epsilon,
+            ),
+        };
+
+        let precipitation_map = self.precipitation_map.map(|precipitation_map| {
+            precipitation_map
+                .iter()
+                .map(|(particle, precipitation)| (*particle, *precipitation))
+                .collect::<ParticleMap<f64>>()
+        });
+        let particle_map = build_drainage_basin(
+            &terrain_map,
+            &voronoi,
+            self.boundary_policy,
+            self.routing_mode,
+            self.sea_level,
+            |particle| runoff(precipitation_map.as_ref(), particle),
+        )?;
+        let unfilled_elevations = match self.depression_handling {
+            DepressionHandling::Keep => None,
+            DepressionHandling::Fill { .. } => Some(
+                self.elevation_map
+                    .iter()
+                    .map(|(particle, elevation)| (*particle, *elevation))
+                    .collect::<HashMap<Particle, f64>>(),
+            ),
+        };
+        let inflows = index_inflows(&particle_map);
+
+        Ok(DrainageMap {
+            particle_map,
+            river_width_model: self
+                .river_width_model
+                .unwrap_or_else(|| Box::new(PowerLawWidth::square_root(self.river_strength))),
+            river_strength: self.river_strength,
+            river_ignoreable_width_strength: self.river_ignoreable_width_strength,
+            hydraulic_model: self.hydraulic_model,
+            boundary_policy: self.boundary_policy,
+            routing_mode: self.routing_mode,
+            sea_level: self.sea_level,
+            depression_handling: self.depression_handling,
+            unfilled_elevations,
+            inflows,
+            precipitation_map,
+            voronoi,
+        })
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet};
+
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{
+    boundary::{Boundary, BoundaryPolicy, Wrapping},
+    voronoi::VoronoiCache,
+};
+
+use super::{
+    error::DrainageError,
+    flat::resolve_flats,
+    map::{
+        accumulate_drainage_area, assemble_nodes, build_streams, compare_sites, compute_receivers,
+        select_trunks, InternalNode,
+    },
+    node::DrainageBasinNode,
+    routing::RoutingMode,
+};
+
+/// Axis-aligned region of the world owned by a chunk. A particle belongs to the chunk
+/// whose bounds contain its site; the maximum edges are exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkBounds {
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+}
+
+impl ChunkBounds {
+    pub fn new(min: (f64, f64), max: (f64, f64)) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, site: (f64, f64)) -> bool {
+        (self.min.0..self.max.0).contains(&site.0) && (self.min.1..self.max.1).contains(&site.1)
+    }
+}
+
+/// Routing options of a chunk, matching the ones of
+/// [`DrainageMapBuilder`](super::builder::DrainageMapBuilder).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChunkParams {
+    /// Applies to the edges of the elevation map given to the chunk.
+    pub boundary_policy: BoundaryPolicy,
+    pub routing_mode: RoutingMode,
+    pub sea_level: Option<f64>,
+}
+
+/// Drainage area crossing a chunk edge from `from` into `to`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundaryFlux {
+    pub from: Particle,
+    pub to: Particle,
+    pub drainage_area: f64,
+}
+
+/// Drainage of a single chunk of an unbounded world.
+///
+/// Receivers are computed from the chunk and its surroundings, so they are the same as in
+/// a whole-map computation as long as every flat reaching into the chunk lies within the
+/// elevation map given to it. Drainage area only accounts for the chunk itself until the
+/// fluxes of the neighboring chunks are merged in with [`merge_chunks`].
+pub struct DrainageChunk {
+    bounds: ChunkBounds,
+    params: ChunkParams,
+    nodes: HashMap<Particle, InternalNode>,
+    halo: HashMap<Particle, InternalNode>,
+    drainage_area: HashMap<Particle, f64>,
+    inflows: HashMap<Particle, (Particle, f64)>,
+    /// Particles of other chunks flowing into the halo, with their drainage area, as
+    /// handed out by [`merge_chunks`].
+    halo_inflows: HashMap<Particle, Vec<(Particle, f64)>>,
+    wrapping: Wrapping,
+}
+
+impl DrainageChunk {
+    /// `elevation_map` must cover `bounds` and at least two rings of particles around it.
+    /// Its edges are treated with `params.boundary_policy`, so they should be the edges of
+    /// the world where they are closer than that.
+    pub fn new(
+        elevation_map: &ParticleMap<f64>,
+        bounds: ChunkBounds,
+        params: ChunkParams,
+    ) -> Result<Self, DrainageError> {
+        Self::new_with_voronoi(
+            elevation_map,
+            &VoronoiCache::new(elevation_map),
+            bounds,
+            params,
+        )
+    }
+
+    pub fn new_with_voronoi(
+        elevation_map: &ParticleMap<f64>,
+        voronoi: &VoronoiCache,
+        bounds: ChunkBounds,
+        params: ChunkParams,
+    ) -> Result<Self, DrainageError> {
+        let boundary = Boundary::new(elevation_map, params.boundary_policy);
+        let elevation = |particle: &Particle| elevation_map.get(particle).copied();
+        // Flats are resolved over the chunk and its halo together, so a flat crossing the
+        // chunk edge drains the same way on both sides of it.
+        let mut receivers = compute_receivers(
+            elevation,
+            voronoi,
+            &boundary,
+            params.boundary_policy,
+            params.routing_mode,
+            params.sea_level,
+            elevation_map.iter().map(|(particle, _)| *particle),
+        );
+        resolve_flats(
+            elevation,
+            voronoi,
+            &boundary,
+            params.boundary_policy,
+            &mut receivers,
+            |_| false,
+        );
+        let (nodes, halo): (
+            HashMap<Particle, InternalNode>,
+            HashMap<Particle, InternalNode>,
+        ) = receivers
+            .into_iter()
+            .partition(|(particle, _)| bounds.contains(particle.site()));
+        let drainage_area = accumulate_drainage_area(&nodes, |_| 1.0)?;
+
+        Ok(Self {
+            bounds,
+            params,
+            nodes,
+            halo,
+            drainage_area,
+            inflows: HashMap::new(),
+            halo_inflows: HashMap::new(),
+            wrapping: boundary.wrapping(),
+        })
+    }
+
+    pub fn bounds(&self) -> &ChunkBounds {
+        &self.bounds
+    }
+
+    pub fn params(&self) -> &ChunkParams {
+        &self.params
+    }
+
+    /// Drainage area leaving the chunk through its edges.
+    pub fn outflows(&self) -> Vec<BoundaryFlux> {
+        let mut outflows = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| !self.nodes.contains_key(&node.flow_to))
+            .filter_map(|(particle, node)| {
+                Some(BoundaryFlux {
+                    from: *particle,
+                    to: node.flow_to,
+                    drainage_area: *self.drainage_area.get(particle)?,
+                })
+            })
+            .collect::<Vec<_>>();
+        outflows.sort_by(|a, b| compare_sites(&a.from, &b.from));
+        outflows
+    }
+
+    /// Drainage area entering the chunk through its edges.
+    pub fn inflows(&self) -> Vec<BoundaryFlux> {
+        self.inflows
+            .iter()
+            .map(|(from, (to, drainage_area))| BoundaryFlux {
+                from: *from,
+                to: *to,
+                drainage_area: *drainage_area,
+            })
+            .collect()
+    }
+
+    /// Adds drainage area entering the chunk and routes it downstream. Returns the
+    /// additional drainage area that leaves the chunk as a result.
+    pub fn apply_inflows(&mut self, inflows: &[BoundaryFlux]) -> Vec<BoundaryFlux> {
+        let mut outflows = Vec::new();
+        for flux in inflows {
+            if !self.nodes.contains_key(&flux.to) {
+                continue;
+            }
+            self.inflows
+                .entry(flux.from)
+                .and_modify(|(_, drainage_area)| *drainage_area += flux.drainage_area)
+                .or_insert((flux.to, flux.drainage_area));
+
+            let mut visited = HashSet::new();
+            let mut current = flux.to;
+            while visited.insert(current) {
+                *self.drainage_area.entry(current).or_insert(0.0) += flux.drainage_area;
+                let flow_to = self.nodes[&current].flow_to;
+                if flow_to == current {
+                    break;
+                }
+                if !self.nodes.contains_key(&flow_to) {
+                    outflows.push(BoundaryFlux {
+                        from: current,
+                        to: flow_to,
+                        drainage_area: flux.drainage_area,
+                    });
+                    break;
+                }
+                current = flow_to;
+            }
+        }
+        outflows
+    }
+
+    /// Assembles the nodes of the chunk. Reaches flowing out of the chunk are only drawn
+    /// as in a whole-map computation once [`merge_chunks`] has told the chunk which
+    /// particles of the other chunks flow into its halo.
+    pub fn to_map(&self) -> ParticleMap<DrainageBasinNode> {
+        let inflows = self
+            .nodes
+            .iter()
+            .filter(|(particle, node)| node.flow_to != **particle)
+            .filter_map(|(particle, node)| {
+                Some((*particle, node.flow_to, *self.drainage_area.get(particle)?))
+            })
+            .chain(
+                self.inflows
+                    .iter()
+                    .map(|(from, (to, drainage_area))| (*from, *to, *drainage_area)),
+            )
+            .chain(self.halo_inflows.iter().flat_map(|(to, upstream)| {
+                upstream
+                    .iter()
+                    .map(move |(from, drainage_area)| (*from, *to, *drainage_area))
+            }));
+        let trunks = select_trunks(inflows);
+        let river_paths = build_streams(
+            self.nodes.keys().copied(),
+            |particle| {
+                self.nodes
+                    .get(particle)
+                    .or_else(|| self.halo.get(particle))
+                    .map(|node| node.flow_to)
+            },
+            &trunks,
+            |particle| trunks.contains_key(particle),
+            |from, site| self.wrapping.unwrap_site(from, site),
+        );
+
+        assemble_nodes(&self.nodes, &self.drainage_area, &river_paths)
+    }
+}
+
+/// Exchanges boundary fluxes between adjacent chunks until every chunk accounts for the
+/// drainage area coming from upstream chunks. Fluxes flowing into particles that no chunk
+/// owns leave the world.
+pub fn merge_chunks(chunks: &mut [DrainageChunk]) {
+    let mut pending = chunks
+        .iter()
+        .flat_map(|chunk| chunk.outflows())
+        .collect::<Vec<_>>();
+    let mut remaining_rounds = chunks.iter().map(|chunk| chunk.nodes.len()).sum::<usize>();
+
+    while !pending.is_empty() && remaining_rounds > 0 {
+        remaining_rounds -= 1;
+        let mut next = Vec::new();
+        for chunk in chunks.iter_mut() {
+            let incoming = pending
+                .iter()
+                .filter(|flux| chunk.bounds.contains(flux.to.site()))
+                .copied()
+                .collect::<Vec<_>>();
+            if !incoming.is_empty() {
+                next.extend(chunk.apply_inflows(&incoming));
+            }
+        }
+        next.sort_by(|a, b| compare_sites(&a.from, &b.from));
+        pending = next;
+    }
+
+    // Reaches leaving a chunk join rivers whose other inflows can belong to other chunks,
+    // so every chunk is told what flows into its halo.
+    let halo = chunks
+        .iter()
+        .flat_map(|chunk| chunk.halo.keys().copied())
+        .collect::<HashSet<Particle>>();
+    let mut halo_inflows: HashMap<Particle, Vec<(Particle, f64)>> = HashMap::new();
+    for chunk in chunks.iter() {
+        for (particle, node) in chunk.nodes.iter() {
+            if node.flow_to == *particle || !halo.contains(&node.flow_to) {
+                continue;
+            }
+            if let Some(drainage_area) = chunk.drainage_area.get(particle) {
+                halo_inflows
+                    .entry(node.flow_to)
+                    .or_default()
+                    .push((*particle, *drainage_area));
+            }
+        }
+    }
+    for chunk in chunks.iter_mut() {
+        chunk.halo_inflows = chunk
+            .halo
+            .keys()
+            .filter_map(|particle| Some((*particle, halo_inflows.get(particle)?.clone())))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drainage::{builder::DrainageMapBuilder, map::DrainageMap},
+        test_util::{assert_same_drainage, assert_same_streams, terrain},
+    };
+
+    fn build_whole(elevation_map: &ParticleMap<f64>, params: ChunkParams) -> DrainageMap {
+        let mut builder = DrainageMapBuilder::new(elevation_map)
+            .boundary_policy(params.boundary_policy)
+            .routing_mode(params.routing_mode);
+        if let Some(sea_level) = params.sea_level {
+            builder = builder.sea_level(sea_level);
+        }
+        builder.build().unwrap()
+    }
+
+    /// Splits the map into `columns` by `rows` chunks of `size` particle spacings. Each
+    /// chunk is given its bounds grown by three spacings, which holds at least two rings
+    /// of particles around them, unless the world wraps around.
+    fn build_chunked(
+        elevation_map: &ParticleMap<f64>,
+        params: ChunkParams,
+        size: (f64, f64),
+        (columns, rows): (usize, usize),
+    ) -> DrainageMap {
+        let scale = elevation_map.params().scale;
+        let mut chunks = (0..columns)
+            .flat_map(|column| (0..rows).map(move |row| (column, row)))
+            .map(|(column, row)| {
+                let min = (column as f64 * size.0 * scale, row as f64 * size.1 * scale);
+                let max = (min.0 + size.0 * scale, min.1 + size.1 * scale);
+                let halo = ChunkBounds::new(
+                    (min.0 - 3.0 * scale, min.1 - 3.0 * scale),
+                    (max.0 + 3.0 * scale, max.1 + 3.0 * scale),
+                );
+                let cropped = elevation_map
+                    .iter()
+                    .filter(|(particle, _)| {
+                        params.boundary_policy == BoundaryPolicy::Periodic
+                            || halo.contains(particle.site())
+                    })
+                    .map(|(particle, elevation)| (*particle, *elevation))
+                    .collect::<ParticleMap<f64>>();
+                DrainageChunk::new(&cropped, ChunkBounds::new(min, max), params).unwrap()
+            })
+            .collect::<Vec<_>>();
+        merge_chunks(&mut chunks);
+        DrainageMap::from_chunks(&chunks, 1.0, 0.01)
+    }
+
+    #[test]
+    fn merged_chunks_match_the_whole_map() {
+        let elevation_map = terrain(12.0, 12.0, |x, y| {
+            ((x - 6.0).powi(2) + y * 3.0 + (x * 1.7 + y * 0.9).sin()) * 0.01
+        });
+        for params in [
+            ChunkParams::default(),
+            ChunkParams {
+                boundary_policy: BoundaryPolicy::Open,
+                routing_mode: RoutingMode::LowestNeighbor,
+                sea_level: Some(0.1),
+            },
+            ChunkParams {
+                boundary_policy: BoundaryPolicy::Periodic,
+                ..ChunkParams::default()
+            },
+        ] {
+            let whole = build_whole(&elevation_map, params);
+            let chunked = build_chunked(&elevation_map, params, (4.0, 6.0), (3, 2));
+            assert_same_drainage(&whole, &chunked);
+            assert_same_streams(&whole, &chunked);
+        }
+    }
+
+    #[test]
+    fn flats_crossing_chunk_edges_match_the_whole_map() {
+        // A flat terrace across the edge between the first two columns of chunks.
+        let elevation_map = terrain(12.0, 12.0, |x, y| {
+            if (2.0..6.0).contains(&x) && (3.0..9.0).contains(&y) {
+                0.3
+            } else {
+                (x + (y - 6.0).abs()) * 0.05
+            }
+        });
+        let params = ChunkParams::default();
+        let whole = build_whole(&elevation_map, params);
+        let chunked = build_chunked(&elevation_map, params, (4.0, 6.0), (3, 2));
+        assert_same_drainage(&whole, &chunked);
+        assert_same_streams(&whole, &chunked);
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{
+    boundary::{Boundary, BoundaryPolicy},
+    voronoi::{LowestFirst, VoronoiCache},
+};
+
+use super::{map::compare_sites, node::DrainageBasinInput};
+
+/// What to do with closed depressions before routing water.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DepressionHandling {
+    /// Depressions are kept, and their lowest particles become outlets.
+    #[default]
+    Keep,
+    /// Depressions are filled up to their spill elevation with priority-flood. Filled
+    /// particles are raised by `epsilon` above the particle they drain to, so a positive
+    /// `epsilon` leaves no flats behind.
+    Fill { epsilon: f64 },
+}
+
+/// Raises the particles of closed depressions to their spill elevation.
+///
+/// The flood starts from the particles water can leave the map through: the open edges,
+/// the particles below `sea_level`, or the lowest particle if there are none.
+pub(crate) fn fill_depressions(
+    terrain_map: &ParticleMap<DrainageBasinInput>,
+    voronoi: &VoronoiCache,
+    boundary_policy: BoundaryPolicy,
+    sea_level: Option<f64>,
+    epsilon: f64,
+) -> ParticleMap<DrainageBasinInput> {
+    let boundary = Boundary::new(terrain_map, boundary_policy);
+
+    let mut seeds = terrain_map
+        .iter()
+        .filter(|(particle, input)| {
+            sea_level.is_some_and(|sea_level| input.elevation < sea_level)
+                || (boundary_policy == BoundaryPolicy::Open
+                    && boundary
+                        .resolve(particle, &voronoi.get_or_compute(particle).neighbors)
+                        .outside
+                        > 0)
+        })
+        .map(|(particle, input)| (*particle, input.elevation))
+        .collect::<Vec<_>>();
+    if seeds.is_empty() {
+        seeds.extend(
+            terrain_map
+                .iter()
+                .min_by(|a, b| {
+                    a.1.elevation
+                        .total_cmp(&b.1.elevation)
+                        .then_with(|| compare_sites(a.0, b.0))
+                })
+                .map(|(particle, input)| (*particle, input.elevation)),
+        );
+    }
+
+    let mut closed = seeds
+        .iter()
+        .map(|(particle, _)| *particle)
+        .collect::<HashSet<_>>();
+    let mut open = seeds
+        .into_iter()
+        .map(|(particle, elevation)| LowestFirst {
+            key: elevation,
+            particle,
+        })
+        .collect::<BinaryHeap<_>>();
+    let mut filled = Vec::new();
+
+    while let Some(LowestFirst {
+        key: elevation,
+        particle,
+    }) = open.pop()
+    {
+        filled.push((particle, DrainageBasinInput { elevation }));
+        let neighbors = boundary.resolve(&particle, &voronoi.get_or_compute(&particle).neighbors);
+        for (neighbor, _) in neighbors.inside {
+            let Some(input) = terrain_map.get(&neighbor) else {
+                continue;
+            };
+            if !closed.insert(neighbor) {
+                continue;
+            }
+            open.push(LowestFirst {
+                key: spill(input.elevation, elevation, epsilon),
+                particle: neighbor,
+            });
+        }
+    }
+
+    terrain_map
+        .iter()
+        .filter(|(particle, _)| !closed.contains(particle))
+        .map(|(particle, input)| (*particle, input.clone()))
+        .chain(filled)
+        .collect::<ParticleMap<DrainageBasinInput>>()
+}
+
+/// Elevation a particle is filled up to when the flood reaches it from a particle filled
+/// up to `level`.
+fn spill(elevation: f64, level: f64, epsilon: f64) -> f64 {
+    if elevation <= level {
+        level + epsilon
+    } else {
+        elevation
+    }
+}
+
+/// Fills depressions again after the particles in `edited` changed, and returns the new
+/// filled elevation of every particle where it changed.
+///
+/// `unfilled` gives the elevations after the edits and `filled` the filled elevations
+/// before them; `is_seed` tells whether water leaves the map at a particle, so there must
+/// be such particles for the flood to start from. The flood only covers the particles
+/// connected to the edits below the highest of them, which is where filled elevations
+/// can change, and spreads further while the filled elevations around it disagree with
+/// the ones inside, as happens when `epsilon` raises a flat step by step.
+pub(crate) fn refill_depressions<T>(
+    edited: impl Iterator<Item = Particle>,
+    voronoi: &VoronoiCache,
+    boundary: &Boundary<T>,
+    unfilled: impl Fn(&Particle) -> Option<f64>,
+    filled: impl Fn(&Particle) -> Option<f64>,
+    is_seed: impl Fn(&Particle) -> bool,
+    epsilon: f64,
+) -> HashMap<Particle, f64> {
+    let neighbors = |particle: &Particle| {
+        boundary
+            .resolve(particle, &voronoi.get_or_compute(particle).neighbors)
+            .inside
+            .into_iter()
+            .map(|(neighbor, _)| neighbor)
+            .filter(|neighbor| unfilled(neighbor).is_some())
+            .collect::<Vec<_>>()
+    };
+
+    let mut pending = edited
+        .filter(|particle| unfilled(particle).is_some())
+        .collect::<Vec<_>>();
+    let highest = pending
+        .iter()
+        .filter_map(|particle| Some(unfilled(particle)?.max(filled(particle)?)))
+        .fold(f64::MIN, f64::max);
+    let mut region = pending.iter().copied().collect::<HashSet<_>>();
+    while let Some(particle) = pending.pop() {
+        for neighbor in neighbors(&particle) {
+            if unfilled(&neighbor).is_some_and(|elevation| elevation <= highest)
+                && region.insert(neighbor)
+            {
+                pending.push(neighbor);
+            }
+        }
+    }
+
+    loop {
+        let border = region
+            .iter()
+            .flat_map(neighbors)
+            .filter(|neighbor| !region.contains(neighbor))
+            .collect::<HashSet<_>>();
+
+        // The border keeps its filled elevations and floods the region like the seeds.
+        let mut closed = border.clone();
+        let mut open = border
+            .iter()
+            .filter_map(|particle| Some((*particle, filled(particle)?)))
+            .chain(
+                region
+                    .iter()
+                    .filter(|particle| is_seed(particle))
+                    .filter_map(|particle| Some((*particle, unfilled(particle)?))),
+            )
+            .map(|(particle, key)| {
+                closed.insert(particle);
+                LowestFirst { key, particle }
+            })
+            .collect::<BinaryHeap<_>>();
+        let mut refilled = HashMap::new();
+        while let Some(LowestFirst {
+            key: level,
+            particle,
+        }) = open.pop()
+        {
+            if region.contains(&particle) {
+                refilled.insert(particle, level);
+            }
+            for neighbor in neighbors(&particle) {
+                if !region.contains(&neighbor) || !closed.insert(neighbor) {
+                    continue;
+                }
+                let Some(elevation) = unfilled(&neighbor) else {
+                    continue;
+                };
+                open.push(LowestFirst {
+                    key: spill(elevation, level, epsilon),
+                    particle: neighbor,
+                });
+            }
+        }
+        let current = |particle: &Particle| match refilled.get(particle) {
+            Some(elevation) => Some(*elevation),
+            None if region.contains(particle) => unfilled(particle),
+            None => filled(particle),
+        };
+
+        let stale = border
+            .into_iter()
+            .filter(|particle| !is_seed(particle))
+            .filter(|particle| {
+                let lowest = neighbors(particle)
+                    .iter()
+                    .filter_map(current)
+                    .fold(f64::INFINITY, f64::min);
+                let expected = unfilled(particle).map(|elevation| match lowest.is_finite() {
+                    true => spill(elevation, lowest, epsilon),
+                    false => elevation,
+                });
+                expected != filled(particle)
+            })
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return region
+                .iter()
+                .filter_map(|particle| Some((*particle, current(particle)?)))
+                .filter(|(particle, elevation)| filled(particle) != Some(*elevation))
+                .collect();
+        }
+        region.extend(stale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drainage::{builder::DrainageMapBuilder, map::elevation_to_input},
+        test_util::terrain,
+    };
+
+    fn in_pit(x: f64, y: f64) -> bool {
+        (3.0..5.0).contains(&x) && (3.0..5.0).contains(&y)
+    }
+
+    /// Slope rising away from its lowest corner, with a pit above that corner.
+    fn pitted_slope() -> ParticleMap<f64> {
+        terrain(
+            8.0,
+            8.0,
+            |x, y| {
+                if in_pit(x, y) {
+                    0.2
+                } else {
+                    (x + y) * 0.1
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn depressions_fill_up_to_their_spill_elevation() {
+        let elevation_map = pitted_slope();
+        let voronoi = VoronoiCache::new(&elevation_map);
+        let filled = fill_depressions(
+            &elevation_to_input(&elevation_map),
+            &voronoi,
+            BoundaryPolicy::Closed,
+            None,
+            0.0,
+        );
+        let scale = elevation_map.params().scale;
+        let spill = elevation_map
+            .iter()
+            .filter(|(particle, _)| {
+                let site = particle.site();
+                !in_pit(site.0 / scale, site.1 / scale)
+            })
+            .filter(|(particle, _)| {
+                voronoi
+                    .get_or_compute(particle)
+                    .neighbors
+                    .iter()
+                    .any(|neighbor| {
+                        let site = neighbor.site();
+                        in_pit(site.0 / scale, site.1 / scale)
+                    })
+            })
+            .map(|(_, elevation)| *elevation)
+            .fold(f64::MAX, f64::min);
+
+        for (particle, elevation) in elevation_map.iter() {
+            let site = particle.site();
+            let expected = if in_pit(site.0 / scale, site.1 / scale) {
+                spill
+            } else {
+                *elevation
+            };
+            assert_eq!(filled.get(particle).unwrap().elevation, expected);
+        }
+    }
+
+    #[test]
+    fn filled_maps_drain_to_a_single_outlet() {
+        let elevation_map = pitted_slope();
+        let kept = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        assert!(kept.outlets().count() > 1);
+
+        let filled = DrainageMapBuilder::new(&elevation_map)
+            .depression_handling(DepressionHandling::Fill { epsilon: 1e-3 })
+            .build()
+            .unwrap();
+        assert_eq!(filled.outlets().count(), 1);
+    }
+}
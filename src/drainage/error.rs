@@ -0,0 +1,25 @@
+use std::fmt;
+
+use worley_particle::Particle;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrainageError {
+    /// Receivers form closed loops, so drainage area cannot be accumulated. Each entry
+    /// lists the particles of one loop.
+    Cycle(Vec<Vec<Particle>>),
+}
+
+impl fmt::Display for DrainageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrainageError::Cycle(cycles) => write!(
+                f,
+                "receivers form {} cycle(s) involving {} particle(s)",
+                cycles.len(),
+                cycles.iter().map(|cycle| cycle.len()).sum::<usize>()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DrainageError {}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use worley_particle::Particle;
+
+use crate::{
+    boundary::{Boundary, BoundaryPolicy},
+    voronoi::VoronoiCache,
+};
+
+use super::map::{compare_sites, InternalNode};
+
+/// Routes water across flats following Garbrecht & Martz (1997).
+///
+/// A flat is a connected set of equal-elevation particles that have no lower neighbor.
+/// Each flat particle gets a mask combining the distance towards the flat's outlets
+/// (particles of the same elevation that do drain) with the distance away from the
+/// surrounding higher terrain, and drains to the neighbor with the lowest mask. Flats
+/// without any outlet are closed depressions and are left untouched.
+///
+/// Only particles in `nodes` whose receiver is themselves are considered; `is_drained`
+/// tells whether a particle outside of `nodes` drains somewhere.
+pub(crate) fn resolve_flats<T>(
+    elevation: impl Fn(&Particle) -> Option<f64>,
+    voronoi: &VoronoiCache,
+    boundary: &Boundary<T>,
+    boundary_policy: BoundaryPolicy,
+    nodes: &mut HashMap<Particle, InternalNode>,
+    is_drained: impl Fn(&Particle) -> bool,
+) {
+    let mut equal_neighbors: HashMap<Particle, Vec<Particle>> = HashMap::new();
+    let mut high_edges = Vec::new();
+    let mut open_outlets = HashSet::new();
+
+    let mut candidates = nodes
+        .iter()
+        .filter(|(particle, node)| node.flow_to == **particle)
+        .map(|(particle, _)| *particle)
+        .collect::<Vec<_>>();
+    candidates.sort_by(compare_sites);
+
+    for particle in candidates.iter() {
+        let neighbors = boundary.resolve(particle, &voronoi.get_or_compute(particle).neighbors);
+        if boundary_policy == BoundaryPolicy::Open && neighbors.outside > 0 {
+            open_outlets.insert(*particle);
+            continue;
+        }
+        let Some(own_elevation) = elevation(particle) else {
+            continue;
+        };
+        let mut equal = Vec::new();
+        let mut has_higher = false;
+        for (neighbor, _) in neighbors.inside {
+            match elevation(&neighbor) {
+                Some(e) if e == own_elevation => equal.push(neighbor),
+                Some(e) if e > own_elevation => has_higher = true,
+                _ => {}
+            }
+        }
+        if equal.is_empty() {
+            continue;
+        }
+        equal.sort_by(compare_sites);
+        if has_higher {
+            high_edges.push(*particle);
+        }
+        equal_neighbors.insert(*particle, equal);
+    }
+
+    let drains = |particle: &Particle| match nodes.get(particle) {
+        Some(node) => node.flow_to != *particle || open_outlets.contains(particle),
+        None => is_drained(particle),
+    };
+
+    let flat_cells = candidates
+        .iter()
+        .filter(|particle| equal_neighbors.contains_key(particle))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let low_edge_of = flat_cells
+        .iter()
+        .filter_map(|particle| {
+            let outlet = equal_neighbors[particle]
+                .iter()
+                .find(|neighbor| drains(neighbor))?;
+            Some((*particle, *outlet))
+        })
+        .collect::<HashMap<Particle, Particle>>();
+
+    let towards_lower = breadth_first_distances(
+        flat_cells
+            .iter()
+            .filter(|particle| low_edge_of.contains_key(particle))
+            .copied(),
+        &equal_neighbors,
+    );
+    let from_higher = breadth_first_distances(high_edges.into_iter(), &equal_neighbors);
+
+    let mut component_of = HashMap::new();
+    let mut highest_distance = Vec::new();
+    for particle in flat_cells.iter() {
+        if component_of.contains_key(particle) {
+            continue;
+        }
+        let component = highest_distance.len();
+        let mut highest = 0;
+        let mut queue = VecDeque::from([*particle]);
+        component_of.insert(*particle, component);
+        while let Some(current) = queue.pop_front() {
+            highest = highest.max(from_higher.get(&current).copied().unwrap_or(0));
+            for neighbor in equal_neighbors[&current].iter() {
+                if equal_neighbors.contains_key(neighbor) && !component_of.contains_key(neighbor) {
+                    component_of.insert(*neighbor, component);
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+        highest_distance.push(highest);
+    }
+
+    let mask = |particle: &Particle| -> Option<usize> {
+        let towards = towards_lower.get(particle)?;
+        let away = from_higher.get(particle).map_or(0, |distance| {
+            highest_distance[component_of[particle]] - distance
+        });
+        Some(towards * 2 + away)
+    };
+
+    let receivers = flat_cells
+        .iter()
+        .filter_map(|particle| {
+            if let Some(outlet) = low_edge_of.get(particle) {
+                return Some((*particle, *outlet));
+            }
+            let own_mask = mask(particle)?;
+            equal_neighbors[particle]
+                .iter()
+                .filter_map(|neighbor| Some((*neighbor, mask(neighbor)?)))
+                .filter(|(_, neighbor_mask)| *neighbor_mask < own_mask)
+                .min_by_key(|(_, neighbor_mask)| *neighbor_mask)
+                .map(|(neighbor, _)| (*particle, neighbor))
+        })
+        .collect::<Vec<_>>();
+
+    for (particle, flow_to) in receivers {
+        if let Some(node) = nodes.get_mut(&particle) {
+            node.flow_to = flow_to;
+            node.slope = 0.0;
+        }
+    }
+}
+
+fn breadth_first_distances(
+    sources: impl Iterator<Item = Particle>,
+    equal_neighbors: &HashMap<Particle, Vec<Particle>>,
+) -> HashMap<Particle, usize> {
+    let mut distances = HashMap::new();
+    let mut queue = VecDeque::new();
+    for source in sources {
+        distances.insert(source, 1);
+        queue.push_back(source);
+    }
+    while let Some(current) = queue.pop_front() {
+        let distance = distances[&current];
+        for neighbor in equal_neighbors[&current].iter() {
+            if equal_neighbors.contains_key(neighbor) && !distances.contains_key(neighbor) {
+                distances.insert(*neighbor, distance + 1);
+                queue.push_back(*neighbor);
+            }
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use worley_particle::map::ParticleMap;
+
+    use super::*;
+    use crate::{
+        drainage::{map::compute_receivers, routing::RoutingMode},
+        test_util::terrain,
+    };
+
+    fn receivers(elevation_map: &ParticleMap<f64>) -> HashMap<Particle, InternalNode> {
+        let elevation = |particle: &Particle| elevation_map.get(particle).copied();
+        let voronoi = VoronoiCache::new(elevation_map);
+        let boundary = Boundary::new(elevation_map, BoundaryPolicy::Closed);
+        let mut nodes = compute_receivers(
+            elevation,
+            &voronoi,
+            &boundary,
+            BoundaryPolicy::Closed,
+            RoutingMode::SteepestDescent,
+            None,
+            elevation_map.iter().map(|(particle, _)| *particle),
+        );
+        resolve_flats(
+            elevation,
+            &voronoi,
+            &boundary,
+            BoundaryPolicy::Closed,
+            &mut nodes,
+            |_| false,
+        );
+        nodes
+    }
+
+    #[test]
+    fn flats_drain_to_their_outlet() {
+        // A flat walled in by higher terrain, with one low particle to drain to.
+        let elevation_map = terrain(10.0, 6.0, |x, y| {
+            if (1.0..9.0).contains(&x) && (1.0..5.0).contains(&y) {
+                0.5
+            } else if x < 1.0 && (2.0..3.0).contains(&y) {
+                0.0
+            } else {
+                1.0
+            }
+        });
+        let nodes = receivers(&elevation_map);
+        let voronoi = VoronoiCache::new(&elevation_map);
+
+        for (particle, node) in nodes.iter().filter(|(_, node)| node.elevation == 0.5) {
+            assert_ne!(node.flow_to, *particle);
+            assert!(voronoi
+                .get_or_compute(particle)
+                .neighbors
+                .contains(&node.flow_to));
+            assert!(nodes[&node.flow_to].elevation <= 0.5);
+
+            let mut current = *particle;
+            for _ in 0..nodes.len() {
+                if nodes[&current].elevation < 0.5 {
+                    break;
+                }
+                current = nodes[&current].flow_to;
+            }
+            assert_eq!(nodes[&current].elevation, 0.0);
+        }
+    }
+
+    #[test]
+    fn closed_flats_are_left_as_outlets() {
+        let elevation_map = terrain(6.0, 6.0, |x, y| {
+            if (1.0..5.0).contains(&x) && (1.0..5.0).contains(&y) {
+                0.5
+            } else {
+                1.0
+            }
+        });
+        let nodes = receivers(&elevation_map);
+        for (particle, node) in nodes.iter().filter(|(_, node)| node.elevation == 0.5) {
+            assert_eq!(node.flow_to, *particle);
+        }
+    }
+}
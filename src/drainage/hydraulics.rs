@@ -0,0 +1,169 @@
+use worley_particle::map::rw::ParticleMapAttributeRW;
+
+use super::node::DrainageBasinNode;
+
+/// Estimated channel state of a single node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelHydraulics {
+    pub discharge: f64,
+    pub width: f64,
+    pub depth: f64,
+    pub velocity: f64,
+}
+
+impl ChannelHydraulics {
+    pub fn is_fordable(&self, max_depth: f64, max_velocity: f64) -> bool {
+        self.depth <= max_depth && self.velocity <= max_velocity
+    }
+}
+
+impl ParticleMapAttributeRW for ChannelHydraulics {
+    fn from_strs(s: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(ChannelHydraulics {
+            discharge: s[0].parse::<f64>()?,
+            width: s[1].parse::<f64>()?,
+            depth: s[2].parse::<f64>()?,
+            velocity: s[3].parse::<f64>()?,
+        })
+    }
+
+    fn to_strings(&self) -> Vec<String> {
+        vec![
+            self.discharge.to_string(),
+            self.width.to_string(),
+            self.depth.to_string(),
+            self.velocity.to_string(),
+        ]
+    }
+
+    fn len_strs() -> usize {
+        4
+    }
+}
+
+/// How discharge is turned into channel depth and velocity.
+///
+/// Discharge is `runoff * drainage_area` in both models.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HydraulicModel {
+    /// Manning's equation for a wide rectangular channel, using the node slope.
+    Manning {
+        runoff: f64,
+        roughness: f64,
+        minimum_slope: f64,
+    },
+    /// Leopold–Maddock hydraulic geometry: `depth = c * Q^f`, `velocity = k * Q^m`.
+    HydraulicGeometry {
+        runoff: f64,
+        depth_coefficient: f64,
+        depth_exponent: f64,
+        velocity_coefficient: f64,
+        velocity_exponent: f64,
+    },
+}
+
+impl Default for HydraulicModel {
+    fn default() -> Self {
+        HydraulicModel::Manning {
+            runoff: 1.0,
+            roughness: 0.035,
+            minimum_slope: 1e-4,
+        }
+    }
+}
+
+impl HydraulicModel {
+    pub fn estimate(&self, node: &DrainageBasinNode, width: f64) -> ChannelHydraulics {
+        match *self {
+            HydraulicModel::Manning {
+                runoff,
+                roughness,
+                minimum_slope,
+            } => {
+                let discharge = runoff * node.drainage_area;
+                if discharge <= 0.0 || width <= 0.0 {
+                    return ChannelHydraulics {
+                        discharge,
+                        width,
+                        depth: 0.0,
+                        velocity: 0.0,
+                    };
+                }
+                let slope = node.slope.abs().max(minimum_slope);
+                let depth = (discharge * roughness / (width * slope.sqrt())).powf(0.6);
+                ChannelHydraulics {
+                    discharge,
+                    width,
+                    depth,
+                    velocity: discharge / (width * depth),
+                }
+            }
+            HydraulicModel::HydraulicGeometry {
+                runoff,
+                depth_coefficient,
+                depth_exponent,
+                velocity_coefficient,
+                velocity_exponent,
+            } => {
+                let discharge = runoff * node.drainage_area;
+                ChannelHydraulics {
+                    discharge,
+                    width,
+                    depth: depth_coefficient * discharge.powf(depth_exponent),
+                    velocity: velocity_coefficient * discharge.powf(velocity_exponent),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::node;
+
+    #[test]
+    fn manning_channel_matches_the_equation() {
+        let model = HydraulicModel::Manning {
+            runoff: 1.0,
+            roughness: 0.035,
+            minimum_slope: 1e-4,
+        };
+        let channel = model.estimate(&node(10.0, 0.01), 2.0);
+        assert_eq!(channel.discharge, 10.0);
+        assert!((channel.depth - 1.3990).abs() < 1e-4);
+        assert!((channel.velocity - 3.5740).abs() < 1e-4);
+        // Continuity and Manning's velocity for a wide channel.
+        assert!((channel.width * channel.depth * channel.velocity - 10.0).abs() < 1e-9);
+        let velocity = channel.depth.powf(2.0 / 3.0) * 0.01f64.sqrt() / 0.035;
+        assert!((channel.velocity - velocity).abs() < 1e-9);
+    }
+
+    #[test]
+    fn manning_channel_uses_the_minimum_slope() {
+        let model = HydraulicModel::default();
+        assert_eq!(
+            model.estimate(&node(10.0, 0.0), 2.0),
+            model.estimate(&node(10.0, -1e-4), 2.0)
+        );
+        let dry = model.estimate(&node(0.0, 0.01), 2.0);
+        assert_eq!((dry.depth, dry.velocity), (0.0, 0.0));
+    }
+
+    #[test]
+    fn hydraulic_geometry_follows_its_power_laws() {
+        let model = HydraulicModel::HydraulicGeometry {
+            runoff: 2.0,
+            depth_coefficient: 0.5,
+            depth_exponent: 0.5,
+            velocity_coefficient: 0.25,
+            velocity_exponent: 1.0,
+        };
+        let channel = model.estimate(&node(8.0, 0.01), 1.0);
+        assert_eq!(channel.discharge, 16.0);
+        assert_eq!(channel.depth, 2.0);
+        assert_eq!(channel.velocity, 4.0);
+        assert!(channel.is_fordable(2.0, 4.0));
+        assert!(!channel.is_fordable(1.5, 4.0));
+    }
+}
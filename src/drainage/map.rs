@@ -1,14 +1,48 @@
-use std::collections::HashMap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use worley_particle::{map::ParticleMap, Particle};
 
-use crate::drainage::node::Stream;
+use crate::{
+    boundary::{Boundary, BoundaryPolicy},
+    drainage::node::{midpoint, Stream},
+    rw::write_sorted_to_file,
+    voronoi::VoronoiCache,
+};
 
-use super::node::{DrainageBasinInput, DrainageBasinNode};
+use super::{
+    braid::BraidParams,
+    builder::DrainageMapBuilder,
+    chunk::DrainageChunk,
+    depression::{fill_depressions, refill_depressions, DepressionHandling},
+    error::DrainageError,
+    flat::resolve_flats,
+    hydraulics::{ChannelHydraulics, HydraulicModel},
+    meander::MeanderParams,
+    node::{DrainageBasinInput, DrainageBasinNode},
+    routing::RoutingMode,
+    width::{PowerLawWidth, RiverWidthModel},
+};
 
 pub struct DrainageMap {
-    particle_map: ParticleMap<DrainageBasinNode>,
-    river_strength: f64,
-    river_ignoreable_width_strength: f64,
+    pub(super) particle_map: ParticleMap<DrainageBasinNode>,
+    pub(super) river_width_model: Box<dyn RiverWidthModel>,
+    /// Strength of the square-root width the map was given, whether or not
+    /// `river_width_model` uses it.
+    pub(super) river_strength: f64,
+    pub(super) river_ignoreable_width_strength: f64,
+    pub(super) hydraulic_model: HydraulicModel,
+    pub(super) boundary_policy: BoundaryPolicy,
+    pub(super) routing_mode: RoutingMode,
+    pub(super) sea_level: Option<f64>,
+    pub(super) depression_handling: DepressionHandling,
+    /// Elevations before depressions were filled, kept to fill them again after edits.
+    pub(super) unfilled_elevations: Option<HashMap<Particle, f64>>,
+    /// Particles flowing into each particle, as listed by [`index_inflows`], kept to
+    /// update drainage after edits.
+    pub(super) inflows: HashMap<Particle, Vec<Particle>>,
+    pub(super) precipitation_map: Option<ParticleMap<f64>>,
+    pub(super) voronoi: VoronoiCache,
 }
 
 impl DrainageMap {
@@ -16,201 +50,1167 @@ impl DrainageMap {
         elevation_map: &ParticleMap<f64>,
         river_strength: f64,
         river_ignoreable_width_strength: f64,
+        boundary_policy: BoundaryPolicy,
+    ) -> Result<Self, DrainageError> {
+        DrainageMapBuilder::new(elevation_map)
+            .river_strength(river_strength)
+            .river_ignoreable_width_strength(river_ignoreable_width_strength)
+            .boundary_policy(boundary_policy)
+            .build()
+    }
+
+    pub fn new_with_voronoi(
+        elevation_map: &ParticleMap<f64>,
+        voronoi: &VoronoiCache,
+        river_strength: f64,
+        river_ignoreable_width_strength: f64,
+        boundary_policy: BoundaryPolicy,
+    ) -> Result<Self, DrainageError> {
+        DrainageMapBuilder::new(elevation_map)
+            .voronoi(voronoi)
+            .river_strength(river_strength)
+            .river_ignoreable_width_strength(river_ignoreable_width_strength)
+            .boundary_policy(boundary_policy)
+            .build()
+    }
+
+    /// Builds a drainage map from chunks that have been merged with
+    /// [`merge_chunks`](super::chunk::merge_chunks). The routing options are taken from
+    /// the chunks, which should all share them. Chunks keep depressions and use a runoff
+    /// of 1, and so does [`update_elevations`](Self::update_elevations) on the result.
+    pub fn from_chunks(
+        chunks: &[DrainageChunk],
+        river_strength: f64,
+        river_ignoreable_width_strength: f64,
     ) -> Self {
-        let particle_map_input = elevation_map
+        let particle_map = chunks
             .iter()
-            .map(|(particle, elevation)| {
-                (
-                    *particle,
-                    DrainageBasinInput {
-                        elevation: *elevation,
-                    },
-                )
+            .flat_map(|chunk| {
+                chunk
+                    .to_map()
+                    .iter()
+                    .map(|(particle, node)| (*particle, node.clone()))
+                    .collect::<Vec<_>>()
             })
-            .collect::<ParticleMap<DrainageBasinInput>>();
-
-        let particle_map = build_drainage_basin(&particle_map_input);
+            .collect::<ParticleMap<DrainageBasinNode>>();
+        let params = chunks
+            .first()
+            .map(|chunk| *chunk.params())
+            .unwrap_or_default();
+        let inflows = index_inflows(&particle_map);
 
         Self {
             particle_map,
+            river_width_model: Box::new(PowerLawWidth::square_root(river_strength)),
             river_strength,
             river_ignoreable_width_strength,
+            hydraulic_model: HydraulicModel::default(),
+            boundary_policy: params.boundary_policy,
+            routing_mode: params.routing_mode,
+            sea_level: params.sea_level,
+            depression_handling: DepressionHandling::Keep,
+            unfilled_elevations: None,
+            inflows,
+            precipitation_map: None,
+            voronoi: VoronoiCache::default(),
         }
     }
 
+    pub fn with_river_width_model(mut self, model: impl RiverWidthModel + 'static) -> Self {
+        self.river_width_model = Box::new(model);
+        self
+    }
+
+    pub fn with_hydraulic_model(mut self, model: HydraulicModel) -> Self {
+        self.hydraulic_model = model;
+        self
+    }
+
     pub fn map(&self) -> &ParticleMap<DrainageBasinNode> {
         &self.particle_map
     }
 
+    pub fn outlets(&self) -> impl Iterator<Item = &DrainageBasinNode> {
+        self.particle_map
+            .iter()
+            .map(|(_, node)| node)
+            .filter(|node| node.is_outlet())
+    }
+
+    pub fn voronoi(&self) -> &VoronoiCache {
+        &self.voronoi
+    }
+
+    pub fn is_river(&self, node: &DrainageBasinNode) -> bool {
+        self.river_width(node) >= self.river_ignoreable_width()
+    }
+
+    /// Whether water leaves the map at this outlet, through an open edge or into the sea,
+    /// rather than collecting in a closed depression.
+    pub fn is_mouth(&self, node: &DrainageBasinNode) -> bool {
+        if !node.is_outlet() {
+            return false;
+        }
+        let below_sea = self
+            .sea_level
+            .is_some_and(|sea_level| node.elevation < sea_level);
+        let on_open_edge = self.boundary_policy == BoundaryPolicy::Open
+            && self
+                .voronoi
+                .get_or_compute(&node.particle)
+                .neighbors
+                .iter()
+                .any(|neighbor| self.particle_map.get(neighbor).is_none());
+        below_sea || on_open_edge
+    }
+
+    /// Strahler order of every river node. Sources are of order 1, and the order grows by
+    /// one where two rivers of the same highest order meet.
+    pub fn stream_orders(&self) -> HashMap<Particle, usize> {
+        let mut inflows: HashMap<Particle, Vec<Particle>> = HashMap::new();
+        for (particle, node) in self.particle_map.iter() {
+            if !node.is_outlet() && self.is_river(node) {
+                if let Some(receiver) = self.particle_map.get(&node.flow_to) {
+                    if self.is_river(receiver) {
+                        inflows.entry(node.flow_to).or_default().push(*particle);
+                    }
+                }
+            }
+        }
+
+        let mut remaining = inflows
+            .iter()
+            .map(|(particle, upstream)| (*particle, upstream.len()))
+            .collect::<HashMap<Particle, usize>>();
+        let mut pending = self
+            .particle_map
+            .iter()
+            .filter(|(particle, node)| self.is_river(node) && !remaining.contains_key(particle))
+            .map(|(particle, _)| *particle)
+            .collect::<Vec<_>>();
+
+        let mut orders = HashMap::new();
+        while let Some(particle) = pending.pop() {
+            let upstream_orders = inflows
+                .get(&particle)
+                .into_iter()
+                .flatten()
+                .map(|upstream| orders[upstream])
+                .collect::<Vec<usize>>();
+            let highest = upstream_orders.iter().copied().max().unwrap_or(0);
+            let order = match upstream_orders.iter().filter(|o| **o == highest).count() {
+                0 => 1,
+                1 => highest,
+                _ => highest + 1,
+            };
+            orders.insert(particle, order);
+
+            let flow_to = self
+                .particle_map
+                .get(&particle)
+                .filter(|node| !node.is_outlet())
+                .map(|node| node.flow_to);
+            if let Some(count) = flow_to.and_then(|flow_to| remaining.get_mut(&flow_to)) {
+                *count -= 1;
+                if *count == 0 {
+                    pending.extend(flow_to);
+                }
+            }
+        }
+
+        orders
+    }
+
+    pub fn river_width_model(&self) -> &dyn RiverWidthModel {
+        self.river_width_model.as_ref()
+    }
+
+    /// Strength of the square-root river width the map was built or loaded with. Rivers
+    /// are sized by [`river_width_model`](Self::river_width_model), which differs from
+    /// `PowerLawWidth::square_root(river_strength)` once another model is set.
+    #[deprecated(note = "rivers are sized by `river_width_model`")]
     pub fn river_strength(&self) -> f64 {
         self.river_strength
     }
 
+    pub fn river_width(&self, node: &DrainageBasinNode) -> f64 {
+        self.river_width_model.width(node)
+    }
+
     pub fn river_ignoreable_width(&self) -> f64 {
         self.river_ignoreable_width_strength * self.particle_map.params().scale
     }
 
-    pub fn save_to_file(&self, file_path: &str) {
+    pub fn hydraulic_model(&self) -> &HydraulicModel {
+        &self.hydraulic_model
+    }
+
+    pub fn hydraulics(&self, particle: &Particle) -> Option<ChannelHydraulics> {
+        let node = self.particle_map.get(particle)?;
+        Some(self.hydraulic_model.estimate(node, self.river_width(node)))
+    }
+
+    pub fn hydraulics_map(&self) -> ParticleMap<ChannelHydraulics> {
         self.particle_map
-            .write_to_file(file_path)
-            .expect("Error writing drainage map");
+            .iter()
+            .map(|(particle, node)| {
+                (
+                    *particle,
+                    self.hydraulic_model.estimate(node, self.river_width(node)),
+                )
+            })
+            .collect::<ParticleMap<ChannelHydraulics>>()
     }
 
+    pub fn save_to_file(&self, file_path: &str) {
+        write_sorted_to_file(&self.particle_map, file_path).expect("Error writing drainage map");
+    }
+
+    pub fn save_hydraulics_to_file(&self, file_path: &str) {
+        write_sorted_to_file(&self.hydraulics_map(), file_path)
+            .expect("Error writing hydraulics map");
+    }
+
+    /// Reads a map written by [`save_to_file`](Self::save_to_file). Files written before
+    /// streams got their current columns and nodes their elevation have a different
+    /// layout and are not read, which returns `None`.
+    ///
+    /// The file holds neither the depression handling nor the precipitation map, so
+    /// [`update_elevations`](Self::update_elevations) keeps depressions and uses a runoff
+    /// of 1 on a loaded map.
     pub fn load_from_file(
         file_path: &str,
         river_strength: f64,
         river_ignoreable_width_strength: f64,
     ) -> Option<Self> {
         let particle_map = ParticleMap::<DrainageBasinNode>::read_from_file(file_path).ok()?;
+        let inflows = index_inflows(&particle_map);
 
         Some(Self {
             particle_map,
+            river_width_model: Box::new(PowerLawWidth::square_root(river_strength)),
             river_strength,
             river_ignoreable_width_strength,
+            hydraulic_model: HydraulicModel::default(),
+            boundary_policy: BoundaryPolicy::Closed,
+            routing_mode: RoutingMode::default(),
+            sea_level: None,
+            depression_handling: DepressionHandling::Keep,
+            unfilled_elevations: None,
+            inflows,
+            precipitation_map: None,
+            voronoi: VoronoiCache::default(),
         })
     }
 
-    pub fn collides_with_river(&self, x: f64, y: f64) -> bool {
-        let radius = self.particle_map.params().scale * 2.0;
-        let binding = Particle::from_inside_radius(x, y, *self.particle_map.params(), radius);
-        let particles = binding
+    pub fn apply_meanders(&mut self, params: &MeanderParams) {
+        self.particle_map = self
+            .particle_map
             .iter()
-            .filter_map(|particle| self.particle_map.get(particle));
-        for node in particles {
-            let river_width = node.river_width(self.river_strength);
-            if river_width < self.river_ignoreable_width() {
-                continue;
+            .map(|(particle, node)| {
+                let amplitude = params.amplitude_of(node, self.river_width(node));
+                let mut node = node.clone();
+                if amplitude != 0.0 {
+                    node.main_river = node.main_river.meander(amplitude);
+                }
+                (*particle, node)
+            })
+            .collect::<ParticleMap<DrainageBasinNode>>();
+        self.rejoin_tributaries();
+    }
+
+    pub fn apply_braiding(&mut self, params: &BraidParams) {
+        self.particle_map = self
+            .particle_map
+            .iter()
+            .map(|(particle, node)| {
+                let river_width = self.river_width(node);
+                let hydraulics = self.hydraulic_model.estimate(node, river_width);
+                let mut node = node.clone();
+                if params.applies_to(node.slope, &hydraulics) {
+                    node.main_river = node
+                        .main_river
+                        .braid(params.channels, params.spread * river_width);
+                }
+                (*particle, node)
+            })
+            .collect::<ParticleMap<DrainageBasinNode>>();
+        self.rejoin_tributaries();
+    }
+
+    /// The trunk of each node, as picked when building streams.
+    fn trunks(&self) -> HashMap<Particle, Particle> {
+        select_trunks(
+            self.particle_map
+                .iter()
+                .filter(|(_, node)| !node.is_outlet())
+                .map(|(particle, node)| (*particle, node.flow_to, node.drainage_area)),
+        )
+    }
+
+    /// Moves the end of every tributary onto the middle of the trunk reach it joins, which
+    /// meandering and braiding shift off the straight reach.
+    fn rejoin_tributaries(&mut self) {
+        let trunks = self.trunks();
+        let boundary = Boundary::new(&self.particle_map, self.boundary_policy);
+        let rejoined = self
+            .particle_map
+            .iter()
+            .filter_map(|(particle, node)| {
+                let trunk = trunks.get(&node.flow_to)?;
+                let receiver = self.particle_map.get(&node.flow_to)?;
+                if node.is_outlet() || receiver.is_outlet() || trunk == particle {
+                    return None;
+                }
+                // The trunk reach is drawn around the trunk site, which may lie across a
+                // periodic edge from the tributary.
+                let start = node.main_river.evaluate(0.0);
+                let site = trunk.site();
+                let unwrapped = boundary.unwrap_site(start, site);
+                let shift = (unwrapped.0 - site.0, unwrapped.1 - site.1);
+                let (junction, direction) = self
+                    .particle_map
+                    .get(trunk)?
+                    .main_river
+                    .junction((start.0 - shift.0, start.1 - shift.1));
+                let junction = (junction.0 + shift.0, junction.1 + shift.1);
+                Some((*particle, node.main_river.rejoin(junction, direction)))
+            })
+            .collect::<HashMap<Particle, Stream>>();
+
+        self.particle_map = self
+            .particle_map
+            .iter()
+            .map(|(particle, node)| {
+                let mut node = node.clone();
+                if let Some(stream) = rejoined.get(particle) {
+                    node.main_river = stream.clone();
+                }
+                (*particle, node)
+            })
+            .collect::<ParticleMap<DrainageBasinNode>>();
+    }
+
+    /// Applies local elevation edits without rebuilding the whole map.
+    ///
+    /// Only the edited particles, their neighbors and the flats they touch get new
+    /// receivers, drainage area is accumulated again below every rerouted particle along
+    /// its old and new downstream paths, and streams are rebuilt around the touched nodes.
+    /// Everything else is read from the map as it is. Meanders and braids on rebuilt
+    /// streams have to be applied again. Depressions are filled again and drainage area is
+    /// weighted by precipitation as when the map was built. Without a sea level or open
+    /// edges, depressions are filled from the lowest particle, which an edit can move, so
+    /// they are filled again over the whole map.
+    ///
+    /// Receivers forming a loop are reported as an error, and the map is left unchanged.
+    pub fn update_elevations(&mut self, changes: &[(Particle, f64)]) -> Result<(), DrainageError> {
+        let edits = changes
+            .iter()
+            .filter(|(particle, _)| self.particle_map.get(particle).is_some())
+            .copied()
+            .collect::<HashMap<Particle, f64>>();
+        let boundary = Boundary::new(&self.particle_map, self.boundary_policy);
+        let changes = match (self.depression_handling, &self.unfilled_elevations) {
+            (DepressionHandling::Fill { epsilon }, Some(unfilled_elevations)) => {
+                self.refilled_elevations(&boundary, &edits, unfilled_elevations, epsilon)
+            }
+            _ => edits.clone(),
+        };
+        if changes.is_empty() {
+            if let Some(unfilled_elevations) = &mut self.unfilled_elevations {
+                unfilled_elevations.extend(edits);
             }
+            return Ok(());
+        }
+
+        let elevation = |particle: &Particle| {
+            changes
+                .get(particle)
+                .copied()
+                .or_else(|| self.particle_map.get(particle).map(|node| node.elevation))
+        };
+        let affected = changes
+            .keys()
+            .flat_map(|particle| {
+                std::iter::once(*particle)
+                    .chain(self.voronoi.get_or_compute(particle).neighbors.clone())
+            })
+            .filter(|particle| self.particle_map.get(particle).is_some())
+            .collect::<HashSet<Particle>>();
+
+        let mut receivers = compute_receivers(
+            elevation,
+            &self.voronoi,
+            &boundary,
+            self.boundary_policy,
+            self.routing_mode,
+            self.sea_level,
+            affected.iter().copied(),
+        );
+        let mut frontier = affected.iter().copied().collect::<Vec<_>>();
+        while !frontier.is_empty() {
+            let flat_neighbors = frontier
+                .iter()
+                .filter(|particle| {
+                    affected.contains(particle) || receivers[particle].flow_to == **particle
+                })
+                .flat_map(|particle| {
+                    let own_elevation = receivers[particle].elevation;
+                    self.voronoi
+                        .get_or_compute(particle)
+                        .neighbors
+                        .clone()
+                        .into_iter()
+                        .filter(move |neighbor| elevation(neighbor) == Some(own_elevation))
+                })
+                .filter(|neighbor| !receivers.contains_key(neighbor))
+                .collect::<HashSet<Particle>>();
+            let expanded = compute_receivers(
+                elevation,
+                &self.voronoi,
+                &boundary,
+                self.boundary_policy,
+                self.routing_mode,
+                self.sea_level,
+                flat_neighbors.into_iter(),
+            );
+            frontier = expanded.keys().copied().collect();
+            receivers.extend(expanded);
+        }
+        resolve_flats(
+            elevation,
+            &self.voronoi,
+            &boundary,
+            self.boundary_policy,
+            &mut receivers,
+            |particle| {
+                self.particle_map
+                    .get(particle)
+                    .is_some_and(|node| node.flow_to != *particle)
+            },
+        );
 
-            if node.main_river.collides(x, y, river_width) {
-                return true;
+        let previous_flow_to = |particle: &Particle| {
+            self.particle_map
+                .get(particle)
+                .map_or(*particle, |node| node.flow_to)
+        };
+        let flow_to = |particle: &Particle| match receivers.get(particle) {
+            Some(node) => node.flow_to,
+            None => previous_flow_to(particle),
+        };
+        let rerouted = receivers
+            .iter()
+            .filter(|(particle, node)| node.flow_to != previous_flow_to(particle))
+            .map(|(particle, _)| *particle)
+            .collect::<Vec<_>>();
+
+        // Inflows of the receivers rerouted particles leave and join, applied to the map's
+        // index once the update succeeds.
+        let mut inflows: HashMap<Particle, Vec<Particle>> = HashMap::new();
+        for particle in rerouted.iter() {
+            let (previous, next) = (previous_flow_to(particle), flow_to(particle));
+            for receiver in [previous, next] {
+                if receiver != *particle {
+                    inflows
+                        .entry(receiver)
+                        .or_insert_with(|| inflows_of(&self.inflows, &receiver).to_vec());
+                }
+            }
+            if let Some(upstream) = inflows.get_mut(&previous) {
+                upstream.retain(|upstream| upstream != particle);
+            }
+            if let Some(upstream) = inflows.get_mut(&next).filter(|_| next != *particle) {
+                let (Ok(index) | Err(index)) =
+                    upstream.binary_search_by(|upstream| compare_sites(upstream, particle));
+                upstream.insert(index, *particle);
             }
         }
-        false
+        let upstream_of = |particle: &Particle| match inflows.get(particle) {
+            Some(upstream) => upstream.as_slice(),
+            None => inflows_of(&self.inflows, particle),
+        };
+
+        // Drainage area changes below every rerouted particle, along both its old and its
+        // new downstream paths. Those particles are accumulated again on the new receivers.
+        let mut downstream = HashSet::new();
+        collect_downstream(&rerouted, previous_flow_to, &mut downstream);
+        collect_downstream(&rerouted, flow_to, &mut downstream);
+        let precipitation_map = self.precipitation_map.as_ref();
+        let drainage_area = reaccumulate_drainage_area(
+            &downstream,
+            flow_to,
+            upstream_of,
+            |particle| {
+                self.particle_map
+                    .get(particle)
+                    .map_or(0.0, |node| node.area * runoff(precipitation_map, particle))
+            },
+            |particle| {
+                self.particle_map
+                    .get(particle)
+                    .map_or(0.0, |node| node.drainage_area)
+            },
+        )?;
+
+        let rebuilt = receivers
+            .keys()
+            .chain(downstream.iter())
+            .flat_map(|particle| {
+                std::iter::once(*particle).chain(upstream_of(particle).iter().copied())
+            })
+            .collect::<HashSet<Particle>>();
+        // Trunks are picked among all the inflows of a receiver, most of which may not
+        // have been rebuilt.
+        let trunks = select_trunks(
+            rebuilt
+                .iter()
+                .map(flow_to)
+                .collect::<HashSet<Particle>>()
+                .into_iter()
+                .flat_map(|receiver| {
+                    upstream_of(&receiver)
+                        .iter()
+                        .map(move |particle| (*particle, receiver))
+                })
+                .filter_map(|(particle, receiver)| {
+                    let area = match drainage_area.get(&particle) {
+                        Some(area) => *area,
+                        None => self.particle_map.get(&particle)?.drainage_area,
+                    };
+                    Some((particle, receiver, area))
+                }),
+        );
+        let river_paths = build_streams(
+            rebuilt.iter().copied(),
+            |particle| Some(flow_to(particle)),
+            &trunks,
+            |particle| !upstream_of(particle).is_empty(),
+            |from, site| boundary.unwrap_site(from, site),
+        );
+
+        let particle_map = self
+            .particle_map
+            .iter()
+            .map(|(particle, node)| {
+                if !rebuilt.contains(particle) {
+                    return (*particle, node.clone());
+                }
+                let receiver = receivers.get(particle);
+                let node = DrainageBasinNode {
+                    particle: *particle,
+                    elevation: receiver.map_or(node.elevation, |receiver| receiver.elevation),
+                    area: node.area,
+                    drainage_area: drainage_area
+                        .get(particle)
+                        .copied()
+                        .unwrap_or(node.drainage_area),
+                    flow_to: flow_to(particle),
+                    slope: receiver.map_or(node.slope, |receiver| receiver.slope),
+                    main_river: river_paths
+                        .get(particle)
+                        .cloned()
+                        .unwrap_or(Stream::Point(particle.site())),
+                };
+                (*particle, node)
+            })
+            .collect::<ParticleMap<DrainageBasinNode>>();
+
+        for (receiver, upstream) in inflows {
+            if upstream.is_empty() {
+                self.inflows.remove(&receiver);
+            } else {
+                self.inflows.insert(receiver, upstream);
+            }
+        }
+        if let Some(unfilled_elevations) = &mut self.unfilled_elevations {
+            unfilled_elevations.extend(edits);
+        }
+        self.particle_map = particle_map;
+        Ok(())
+    }
+
+    /// Filled elevations that change when `edits` are applied to the elevations before
+    /// depressions were filled.
+    fn refilled_elevations(
+        &self,
+        boundary: &Boundary<DrainageBasinNode>,
+        edits: &HashMap<Particle, f64>,
+        unfilled_elevations: &HashMap<Particle, f64>,
+        epsilon: f64,
+    ) -> HashMap<Particle, f64> {
+        let unfilled = |particle: &Particle| {
+            edits
+                .get(particle)
+                .or_else(|| unfilled_elevations.get(particle))
+                .copied()
+        };
+        let filled =
+            |particle: &Particle| self.particle_map.get(particle).map(|node| node.elevation);
+        let open = self.boundary_policy == BoundaryPolicy::Open;
+        let below_sea = |particle: &Particle| {
+            self.sea_level
+                .zip(unfilled(particle))
+                .is_some_and(|(sea_level, elevation)| elevation < sea_level)
+        };
+        let on_open_edge = |particle: &Particle| {
+            open && boundary
+                .resolve(particle, &self.voronoi.get_or_compute(particle).neighbors)
+                .outside
+                > 0
+        };
+
+        if open || unfilled_elevations.keys().any(below_sea) {
+            return refill_depressions(
+                edits.keys().copied(),
+                &self.voronoi,
+                boundary,
+                unfilled,
+                filled,
+                |particle| below_sea(particle) || on_open_edge(particle),
+                epsilon,
+            );
+        }
+        let terrain_map = unfilled_elevations
+            .keys()
+            .filter_map(|particle| {
+                Some((
+                    *particle,
+                    DrainageBasinInput {
+                        elevation: unfilled(particle)?,
+                    },
+                ))
+            })
+            .collect::<ParticleMap<DrainageBasinInput>>();
+        fill_depressions(
+            &terrain_map,
+            &self.voronoi,
+            self.boundary_policy,
+            self.sea_level,
+            epsilon,
+        )
+        .iter()
+        .filter(|(particle, input)| filled(particle) != Some(input.elevation))
+        .map(|(particle, input)| (*particle, input.elevation))
+        .collect()
+    }
+
+    pub fn collides_with_river(&self, x: f64, y: f64) -> bool {
+        let radius = self.particle_map.params().scale * 2.0;
+        Particle::from_inside_radius(x, y, *self.particle_map.params(), radius)
+            .iter()
+            .filter_map(|particle| self.particle_map.get(particle))
+            .any(|node| {
+                self.is_river(node) && node.main_river.collides(x, y, self.river_width(node))
+            })
     }
+
+    /// The widest river reach covering the point, if any.
+    pub fn river_at(&self, x: f64, y: f64) -> Option<&DrainageBasinNode> {
+        let radius = self.particle_map.params().scale * 2.0;
+        Particle::from_inside_radius(x, y, *self.particle_map.params(), radius)
+            .iter()
+            .filter_map(|particle| self.particle_map.get(particle))
+            .filter(|node| self.is_river(node))
+            .filter(|node| node.main_river.collides(x, y, self.river_width(node)))
+            .max_by(|a, b| self.river_width(a).total_cmp(&self.river_width(b)))
+    }
+
+    /// The widest river reach crossing the segment from `from` to `to`, with the point of
+    /// the segment where it crosses.
+    pub fn river_across(
+        &self,
+        from: (f64, f64),
+        to: (f64, f64),
+    ) -> Option<(&DrainageBasinNode, (f64, f64))> {
+        let middle = midpoint(from, to);
+        let radius =
+            self.particle_map.params().scale * 2.0 + (to.0 - from.0).hypot(to.1 - from.1) / 2.0;
+        Particle::from_inside_radius(middle.0, middle.1, *self.particle_map.params(), radius)
+            .iter()
+            .filter_map(|particle| self.particle_map.get(particle))
+            .filter(|node| self.is_river(node))
+            .filter_map(|node| {
+                Some((
+                    node,
+                    node.main_river.crossing(from, to, self.river_width(node))?,
+                ))
+            })
+            .max_by(|a, b| self.river_width(a.0).total_cmp(&self.river_width(b.0)))
+    }
+}
+
+pub(crate) fn elevation_to_input(
+    elevation_map: &ParticleMap<f64>,
+) -> ParticleMap<DrainageBasinInput> {
+    elevation_map
+        .iter()
+        .map(|(particle, elevation)| {
+            (
+                *particle,
+                DrainageBasinInput {
+                    elevation: *elevation,
+                },
+            )
+        })
+        .collect::<ParticleMap<DrainageBasinInput>>()
 }
 
-fn build_drainage_basin(
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InternalNode {
+    pub elevation: f64,
+    pub area: f64,
+    pub flow_to: Particle,
+    pub slope: f64,
+}
+
+pub(crate) fn build_drainage_basin(
     terrain_map: &ParticleMap<DrainageBasinInput>,
-) -> ParticleMap<DrainageBasinNode> {
-    #[derive(Debug, Clone, PartialEq)]
-    struct InternalNode {
-        area: f64,
-        flow_to: Particle,
-        slope: f64,
-    }
+    voronoi: &VoronoiCache,
+    boundary_policy: BoundaryPolicy,
+    routing_mode: RoutingMode,
+    sea_level: Option<f64>,
+    runoff: impl Fn(&Particle) -> f64 + Sync,
+) -> Result<ParticleMap<DrainageBasinNode>, DrainageError> {
+    let boundary = Boundary::new(terrain_map, boundary_policy);
+    let elevation = |particle: &Particle| terrain_map.get(particle).map(|input| input.elevation);
 
-    let nodes = terrain_map
+    let mut nodes = compute_receivers(
+        elevation,
+        voronoi,
+        &boundary,
+        boundary_policy,
+        routing_mode,
+        sea_level,
+        terrain_map.iter().map(|(particle, _)| *particle),
+    );
+    resolve_flats(
+        elevation,
+        voronoi,
+        &boundary,
+        boundary_policy,
+        &mut nodes,
+        |_| false,
+    );
+    let drainage_area = accumulate_drainage_area(&nodes, runoff)?;
+    let inflows = nodes
         .iter()
-        .map(|(&particle, input)| {
-            let voronoi = particle.calculate_voronoi();
-            let area = voronoi.area();
+        .filter(|(particle, node)| node.flow_to != **particle)
+        .filter_map(|(particle, node)| {
+            Some((*particle, node.flow_to, *drainage_area.get(particle)?))
+        });
+    let trunks = select_trunks(inflows);
+    let river_paths = build_streams(
+        nodes.keys().copied(),
+        |particle| nodes.get(particle).map(|node| node.flow_to),
+        &trunks,
+        |particle| trunks.contains_key(particle),
+        |from, site| boundary.unwrap_site(from, site),
+    );
+
+    Ok(assemble_nodes(&nodes, &drainage_area, &river_paths))
+}
+
+/// Picks the best lower neighbor of each particle for `routing_mode` as its receiver,
+/// breaking ties by site so the result does not depend on the neighbor order. Particles
+/// below `sea_level` or without any lower neighbor flow to themselves; flats among the
+/// latter are routed by [`resolve_flats`]. Particles `elevation` gives no elevation for
+/// are skipped.
+pub(crate) fn compute_receivers<T: Sync>(
+    elevation: impl Fn(&Particle) -> Option<f64> + Sync,
+    voronoi: &VoronoiCache,
+    boundary: &Boundary<T>,
+    boundary_policy: BoundaryPolicy,
+    routing_mode: RoutingMode,
+    sea_level: Option<f64>,
+    particles: impl Iterator<Item = Particle>,
+) -> HashMap<Particle, InternalNode> {
+    let particles = particles.collect::<Vec<_>>();
+
+    let particles = par_iter!(particles);
+
+    particles
+        .filter_map(|&particle| {
+            let own_elevation = elevation(&particle)?;
+            let cell = voronoi.get_or_compute(&particle);
+            let area = cell.area;
+            let neighbors = boundary.resolve(&particle, &cell.neighbors);
+            let below_sea = sea_level.is_some_and(|sea_level| own_elevation < sea_level);
+            if below_sea || (boundary_policy == BoundaryPolicy::Open && neighbors.outside > 0) {
+                return Some((
+                    particle,
+                    InternalNode {
+                        elevation: own_elevation,
+                        area,
+                        flow_to: particle,
+                        slope: 0.0,
+                    },
+                ));
+            }
             let mut flow_to = None;
+            let mut best_score = 0.0;
             let mut steepest_slope = 0.0;
             let site = particle.site();
-            for neighbor in voronoi.neighbors {
-                if let Some(neighbor_input) = terrain_map.get(&neighbor) {
-                    if neighbor_input.elevation > input.elevation {
+            for (neighbor, neighbor_site) in neighbors.inside {
+                if let Some(neighbor_elevation) = elevation(&neighbor) {
+                    if neighbor_elevation >= own_elevation {
                         continue;
                     }
-                    let neighbor_site = neighbor.site();
                     let distance = (site.0 - neighbor_site.0).hypot(site.1 - neighbor_site.1);
-                    let slope = (neighbor_input.elevation - input.elevation) / distance;
-                    if flow_to.is_some() {
-                        if slope > steepest_slope {
-                            steepest_slope = slope;
-                            flow_to = Some(neighbor);
+                    let drop = neighbor_elevation - own_elevation;
+                    let score = routing_mode.score(drop, distance);
+                    let better = match flow_to {
+                        None => true,
+                        Some(current) => {
+                            score < best_score
+                                || (score == best_score
+                                    && compare_sites(&neighbor, &current).is_lt())
                         }
-                    } else {
-                        steepest_slope = slope;
+                    };
+                    if better {
+                        best_score = score;
+                        steepest_slope = drop / distance;
                         flow_to = Some(neighbor);
                     }
                 }
             }
             if let Some(flow_to) = flow_to {
-                (
+                Some((
                     particle,
                     InternalNode {
+                        elevation: own_elevation,
                         area,
                         flow_to,
                         slope: steepest_slope,
                     },
-                )
+                ))
             } else {
-                (
+                Some((
                     particle,
                     InternalNode {
+                        elevation: own_elevation,
                         area,
                         flow_to: particle,
                         slope: 0.0,
                     },
-                )
+                ))
             }
         })
-        .collect::<ParticleMap<InternalNode>>();
-
-    let mut parent_num = HashMap::new();
+        .collect()
+}
 
-    for (_, node) in nodes.iter() {
-        let flow_to: Particle = node.flow_to;
-        parent_num
-            .entry(flow_to)
-            .and_modify(|e| *e += 1)
-            .or_insert(1);
+/// Accumulates drainage area, weighted by `runoff`, along the receivers. Water flowing to
+/// a particle outside of `nodes` leaves the accumulation there.
+///
+/// Nodes are processed in topological order, level by level: a node is computed once all
+/// of its inflows are, so the nodes of one level are independent of each other. Inflows
+/// are summed in the order of their sites, which keeps the result identical whatever order
+/// the levels are computed in. Nodes that never become ready lie on or below a cycle of
+/// receivers, which is reported as an error.
+pub(crate) fn accumulate_drainage_area(
+    nodes: &HashMap<Particle, InternalNode>,
+    runoff: impl Fn(&Particle) -> f64 + Sync,
+) -> Result<HashMap<Particle, f64>, DrainageError> {
+    let mut inflows: HashMap<Particle, Vec<Particle>> = HashMap::new();
+    for (particle, node) in nodes.iter() {
+        if node.flow_to != *particle && nodes.contains_key(&node.flow_to) {
+            inflows.entry(node.flow_to).or_default().push(*particle);
+        }
+    }
+    for upstream in inflows.values_mut() {
+        upstream.sort_by(compare_sites);
     }
 
+    let mut remaining = inflows
+        .iter()
+        .map(|(particle, upstream)| (*particle, upstream.len()))
+        .collect::<HashMap<Particle, usize>>();
+    let mut level = nodes
+        .keys()
+        .filter(|particle| !remaining.contains_key(particle))
+        .copied()
+        .collect::<Vec<_>>();
+
     let mut drainage_area = HashMap::new();
 
-    for (origin_particle, _) in nodes.iter() {
-        let mut current = *origin_particle;
-        if parent_num.contains_key(&current) {
-            continue;
+    while !level.is_empty() {
+        let level_iter = par_iter!(level);
+
+        let computed = level_iter
+            .map(|particle| {
+                let upstream_area = inflows.get(particle).map_or(0.0, |upstream| {
+                    upstream
+                        .iter()
+                        .map(|upstream| drainage_area[upstream])
+                        .sum()
+                });
+                (
+                    *particle,
+                    nodes[particle].area * runoff(particle) + upstream_area,
+                )
+            })
+            .collect::<Vec<(Particle, f64)>>();
+
+        let mut next_level = Vec::new();
+        for (particle, area) in computed {
+            drainage_area.insert(particle, area);
+            let flow_to = nodes[&particle].flow_to;
+            if flow_to == particle {
+                continue;
+            }
+            if let Some(count) = remaining.get_mut(&flow_to) {
+                *count -= 1;
+                if *count == 0 {
+                    next_level.push(flow_to);
+                }
+            }
         }
-        loop {
-            let current_area = nodes.get(&current).unwrap().area;
-            drainage_area
-                .entry(current)
-                .and_modify(|e| {
-                    *e += current_area;
-                })
-                .or_insert(current_area);
+        level = next_level;
+    }
 
-            let flow_to = nodes.get(&current).unwrap().flow_to;
+    if drainage_area.len() < nodes.len() {
+        let pending = nodes
+            .keys()
+            .filter(|particle| !drainage_area.contains_key(particle))
+            .copied()
+            .collect();
+        return Err(DrainageError::Cycle(find_cycles(pending, |particle| {
+            nodes.get(particle).map(|node| node.flow_to)
+        })));
+    }
 
-            if flow_to == current {
-                break;
+    Ok(drainage_area)
+}
+
+/// Collects the receiver cycles reached from the `pending` particles, which were never
+/// accumulated. `flow_to` gives the receiver of a particle, if it is known.
+fn find_cycles(
+    mut pending: Vec<Particle>,
+    flow_to: impl Fn(&Particle) -> Option<Particle>,
+) -> Vec<Vec<Particle>> {
+    pending.sort_by(compare_sites);
+
+    let mut visited = HashSet::new();
+    let mut cycles = Vec::new();
+    for start in pending {
+        let mut path = Vec::new();
+        let mut on_path = HashMap::new();
+        let mut current = Some(start);
+        while let Some(particle) = current.filter(|particle| visited.insert(*particle)) {
+            on_path.insert(particle, path.len());
+            path.push(particle);
+            current = flow_to(&particle).filter(|next| *next != particle);
+        }
+        if let Some(&index) = current.and_then(|particle| on_path.get(&particle)) {
+            cycles.push(path[index..].to_vec());
+        }
+    }
+    cycles
+}
+
+/// Area each particle contributes to the drainage area: its precipitation if there is a
+/// precipitation map, and 1 otherwise.
+pub(super) fn runoff(precipitation_map: Option<&ParticleMap<f64>>, particle: &Particle) -> f64 {
+    match precipitation_map {
+        Some(precipitation_map) => precipitation_map.get(particle).copied().unwrap_or(0.0),
+        None => 1.0,
+    }
+}
+
+/// Accumulates the drainage area of `particles` again and returns it. `inflows` lists
+/// the particles flowing into a particle in the order of their sites, `contribution`
+/// gives the area a particle adds itself, weighted by its runoff, and `drainage_area`
+/// the unchanged drainage area of the particles outside of `particles`. Receiver cycles
+/// are reported as by [`accumulate_drainage_area`].
+fn reaccumulate_drainage_area<'a>(
+    particles: &HashSet<Particle>,
+    flow_to: impl Fn(&Particle) -> Particle,
+    inflows: impl Fn(&Particle) -> &'a [Particle],
+    contribution: impl Fn(&Particle) -> f64,
+    drainage_area: impl Fn(&Particle) -> f64,
+) -> Result<HashMap<Particle, f64>, DrainageError> {
+    let mut remaining = particles
+        .iter()
+        .map(|particle| {
+            let count = inflows(particle)
+                .iter()
+                .filter(|upstream| particles.contains(upstream))
+                .count();
+            (*particle, count)
+        })
+        .collect::<HashMap<Particle, usize>>();
+    let mut ready = remaining
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(particle, _)| *particle)
+        .collect::<Vec<_>>();
+
+    let mut accumulated = HashMap::new();
+    while let Some(particle) = ready.pop() {
+        let upstream_area = inflows(&particle)
+            .iter()
+            .map(|upstream| match accumulated.get(upstream) {
+                Some(area) => *area,
+                None => drainage_area(upstream),
+            })
+            .sum::<f64>();
+        accumulated.insert(particle, contribution(&particle) + upstream_area);
+        let flow_to = flow_to(&particle);
+        if flow_to == particle {
+            continue;
+        }
+        if let Some(count) = remaining.get_mut(&flow_to) {
+            *count -= 1;
+            if *count == 0 {
+                ready.push(flow_to);
             }
+        }
+    }
 
-            let current_drainage_area = *drainage_area.get(&current).unwrap();
+    if accumulated.len() < particles.len() {
+        let pending = particles
+            .iter()
+            .filter(|particle| !accumulated.contains_key(particle))
+            .copied()
+            .collect();
+        return Err(DrainageError::Cycle(find_cycles(pending, |particle| {
+            particles.contains(particle).then(|| flow_to(particle))
+        })));
+    }
 
-            drainage_area
-                .entry(flow_to)
-                .and_modify(|e| {
-                    *e += current_drainage_area;
-                })
-                .or_insert(current_drainage_area);
+    Ok(accumulated)
+}
 
-            if *parent_num.get(&flow_to).unwrap() > 1 {
-                parent_num.entry(flow_to).and_modify(|e| *e -= 1);
+/// Walks down from every particle in `starts` along `flow_to` and collects the particles
+/// below them.
+fn collect_downstream(
+    starts: &[Particle],
+    flow_to: impl Fn(&Particle) -> Particle,
+    downstream: &mut HashSet<Particle>,
+) {
+    let mut visited = HashSet::new();
+    for start in starts {
+        let mut current = flow_to(start);
+        while current != *start && visited.insert(current) {
+            downstream.insert(current);
+            let next = flow_to(&current);
+            if next == current {
                 break;
             }
+            current = next;
+        }
+    }
+}
 
-            current = flow_to;
+/// Particles flowing into `particle` according to an inflow index.
+fn inflows_of<'a>(
+    index: &'a HashMap<Particle, Vec<Particle>>,
+    particle: &Particle,
+) -> &'a [Particle] {
+    index.get(particle).map_or(&[], Vec::as_slice)
+}
+
+/// Lists the particles flowing into each node, in the order of their sites.
+pub(super) fn index_inflows(
+    particle_map: &ParticleMap<DrainageBasinNode>,
+) -> HashMap<Particle, Vec<Particle>> {
+    let mut inflows: HashMap<Particle, Vec<Particle>> = HashMap::new();
+    for (particle, node) in particle_map.iter() {
+        if !node.is_outlet() {
+            inflows.entry(node.flow_to).or_default().push(*particle);
         }
     }
+    for upstream in inflows.values_mut() {
+        upstream.sort_by(compare_sites);
+    }
+    inflows
+}
+
+pub(crate) fn compare_sites(a: &Particle, b: &Particle) -> std::cmp::Ordering {
+    let (site_a, site_b) = (a.site(), b.site());
+    site_a
+        .0
+        .total_cmp(&site_b.0)
+        .then(site_a.1.total_cmp(&site_b.1))
+}
 
+/// The inflow with the largest drainage area of each receiver in `inflows`, which lists
+/// `(particle, flow_to, drainage_area)`, ties broken by site. Receivers without an entry
+/// receive no water.
+pub(crate) fn select_trunks(
+    inflows: impl Iterator<Item = (Particle, Particle, f64)>,
+) -> HashMap<Particle, Particle> {
+    let mut trunks: HashMap<Particle, (Particle, f64)> = HashMap::new();
+    for (particle, flow_to, area) in inflows {
+        trunks
+            .entry(flow_to)
+            .and_modify(|trunk| {
+                if trunk.1 < area || (trunk.1 == area && compare_sites(&particle, &trunk.0).is_lt())
+                {
+                    *trunk = (particle, area);
+                }
+            })
+            .or_insert((particle, area));
+    }
+    trunks
+        .into_iter()
+        .map(|(receiver, (trunk, _))| (receiver, trunk))
+        .collect()
+}
+
+/// Builds the river stream of every particle in `particles`.
+///
+/// `flow_to` must give the receivers of `particles` and of the particles they flow into,
+/// and `trunks` the trunks of those receivers, as picked by [`select_trunks`] from all of
+/// their inflows. `has_inflows` tells whether any water flows into a particle; reaches
+/// of particles without inflows are river sources. `unwrap_site(from, site)` returns
+/// where `site` is seen from `from`.
+pub(crate) fn build_streams(
+    particles: impl Iterator<Item = Particle>,
+    flow_to: impl Fn(&Particle) -> Option<Particle>,
+    trunks: &HashMap<Particle, Particle>,
+    has_inflows: impl Fn(&Particle) -> bool,
+    unwrap_site: impl Fn((f64, f64), (f64, f64)) -> (f64, f64),
+) -> HashMap<Particle, Stream> {
     let mut river_paths = HashMap::new();
 
-    for (particle, node) in nodes.iter() {
-        let flow_to = node.flow_to;
-        if flow_to == *particle {
+    for particle in particles {
+        let Some(receiver) = flow_to(&particle).filter(|receiver| *receiver != particle) else {
             continue;
-        }
-        let second_flow_to = nodes.get(&flow_to).unwrap().flow_to;
-        let (site_0, site_1, site_2) = (particle.site(), flow_to.site(), second_flow_to.site());
-        river_paths.insert(*particle, Stream::new(site_0, site_1, site_2));
+        };
+        let second_flow_to = flow_to(&receiver).unwrap_or(receiver);
+        let site_0 = particle.site();
+        let site_1 = unwrap_site(site_0, receiver.site());
+        let site_2 = unwrap_site(site_1, second_flow_to.site());
+        let reach_start = |from: &Particle| {
+            let from_site = unwrap_site(site_1, from.site());
+            if has_inflows(from) {
+                midpoint(from_site, site_1)
+            } else {
+                from_site
+            }
+        };
+        let trunk = trunks
+            .get(&receiver)
+            .filter(|trunk| **trunk != particle && second_flow_to != receiver);
+        let stream = match trunk {
+            Some(trunk) => {
+                Stream::tributary(reach_start(&particle), site_1, site_2, reach_start(trunk))
+            }
+            None if has_inflows(&particle) => Stream::new(site_0, site_1, site_2),
+            None => Stream::from_source(site_0, site_1, site_2),
+        };
+        river_paths.insert(particle, stream);
     }
 
+    river_paths
+}
+
+pub(crate) fn assemble_nodes(
+    nodes: &HashMap<Particle, InternalNode>,
+    drainage_area: &HashMap<Particle, f64>,
+    river_paths: &HashMap<Particle, Stream>,
+) -> ParticleMap<DrainageBasinNode> {
     nodes
         .iter()
         .filter_map(|(particle, node)| {
@@ -218,11 +1218,15 @@ fn build_drainage_basin(
                 *particle,
                 DrainageBasinNode {
                     particle: *particle,
+                    elevation: node.elevation,
                     area: node.area,
                     drainage_area: *drainage_area.get(particle)?,
                     flow_to: node.flow_to,
                     slope: node.slope,
-                    main_river: river_paths.get(particle)?.clone(),
+                    main_river: river_paths
+                        .get(particle)
+                        .cloned()
+                        .unwrap_or(Stream::Point(particle.site())),
                 },
             ))
         })
@@ -245,34 +1249,40 @@ mod visualization {
 
             if focus_range.radius() > 0.1 {
                 for (_, node) in self.map().iter() {
-                    let river_width = node.river_width(self.river_strength());
+                    let river_width = self.river_width(node);
                     if river_width < self.river_ignoreable_width() {
                         continue;
                     }
                     let iter_num = (0.1 / focus_range.radius()).ceil() as usize;
+                    let channel_num = node.main_river.channel_num();
 
-                    let point_0 = node.main_river.evaluate(0.0);
-                    let x0 = rect.map_coord_x(point_0.0, 0.0, area_width as f64);
-                    let y0 = rect.map_coord_y(point_0.1, 0.0, area_height as f64);
+                    for channel in 0..channel_num {
+                        let point_0 = node.main_river.evaluate_channel(channel, 0.0);
+                        let x0 = rect.map_coord_x(point_0.0, 0.0, area_width as f64);
+                        let y0 = rect.map_coord_y(point_0.1, 0.0, area_height as f64);
 
-                    cr.move_to(x0, y0);
+                        cr.move_to(x0, y0);
 
-                    for i in 1..(iter_num + 1) {
-                        let t = i as f64 / iter_num as f64;
+                        for i in 1..(iter_num + 1) {
+                            let t = i as f64 / iter_num as f64;
 
-                        let point_1 = node.main_river.evaluate(t);
-                        let x1 = rect.map_coord_x(point_1.0, 0.0, area_width as f64);
-                        let y1 = rect.map_coord_y(point_1.1, 0.0, area_height as f64);
+                            let point_1 = node.main_river.evaluate_channel(channel, t);
+                            let x1 = rect.map_coord_x(point_1.0, 0.0, area_width as f64);
+                            let y1 = rect.map_coord_y(point_1.1, 0.0, area_height as f64);
 
-                        cr.line_to(x1, y1);
-                    }
+                            cr.line_to(x1, y1);
+                        }
 
-                    cr.set_line_width(
-                        river_width / focus_range.radius() / self.map().params().scale,
-                    );
-                    cr.set_source_rgb(0.0, 0.0, 1.0);
-                    cr.set_line_cap(gtk4::cairo::LineCap::Round);
-                    cr.stroke().expect("Failed to draw edge");
+                        cr.set_line_width(
+                            river_width
+                                / channel_num as f64
+                                / focus_range.radius()
+                                / self.map().params().scale,
+                        );
+                        cr.set_source_rgb(0.0, 0.0, 1.0);
+                        cr.set_line_cap(gtk4::cairo::LineCap::Round);
+                        cr.stroke().expect("Failed to draw edge");
+                    }
                 }
             } else {
                 let img_width = drawing_area.width();
@@ -297,3 +1307,510 @@ mod visualization {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_same_drainage, assert_same_streams, terrain};
+
+    fn edited(elevation_map: &ParticleMap<f64>, changes: &[(Particle, f64)]) -> ParticleMap<f64> {
+        let changes = changes.iter().copied().collect::<HashMap<_, _>>();
+        elevation_map
+            .iter()
+            .map(|(particle, elevation)| {
+                (
+                    *particle,
+                    changes.get(particle).copied().unwrap_or(*elevation),
+                )
+            })
+            .collect()
+    }
+
+    fn assert_update_matches_rebuild(changes: impl Fn(&ParticleMap<f64>) -> Vec<(Particle, f64)>) {
+        assert_update_matches_rebuild_with(DepressionHandling::Keep, false, changes);
+    }
+
+    fn assert_update_matches_rebuild_with(
+        depression_handling: DepressionHandling,
+        with_precipitation: bool,
+        changes: impl Fn(&ParticleMap<f64>) -> Vec<(Particle, f64)>,
+    ) {
+        // A valley along y = 4.5, which its sides join as tributaries.
+        let elevation_map = terrain(10.0, 10.0, |x, y| {
+            (x * 2.0 + (y - 4.5).abs() * 3.0 + (x * 1.3 + y * 0.7).sin()) * 0.01
+        });
+        let precipitation_map = elevation_map
+            .iter()
+            .map(|(particle, _)| (*particle, 0.5 + particle.site().1 * 0.1))
+            .collect::<ParticleMap<f64>>();
+        let build = |elevation_map: &ParticleMap<f64>| {
+            let builder =
+                DrainageMapBuilder::new(elevation_map).depression_handling(depression_handling);
+            match with_precipitation {
+                true => builder.precipitation_map(&precipitation_map),
+                false => builder,
+            }
+            .build()
+            .unwrap()
+        };
+
+        let changes = changes(&elevation_map);
+        let mut updated = build(&elevation_map);
+        updated.update_elevations(&changes).unwrap();
+        let rebuilt = build(&edited(&elevation_map, &changes));
+        assert_same_drainage(&rebuilt, &updated);
+        assert_same_streams(&rebuilt, &updated);
+    }
+
+    fn within(
+        elevation_map: &ParticleMap<f64>,
+        region: impl Fn(f64, f64) -> bool,
+    ) -> Vec<(Particle, f64)> {
+        let scale = elevation_map.params().scale;
+        elevation_map
+            .iter()
+            .filter(|(particle, _)| {
+                let site = particle.site();
+                region(site.0 / scale, site.1 / scale)
+            })
+            .map(|(particle, elevation)| (*particle, *elevation))
+            .collect()
+    }
+
+    #[test]
+    fn raising_matches_rebuild() {
+        assert_update_matches_rebuild(|elevation_map| {
+            within(elevation_map, |x, y| {
+                (3.0..5.0).contains(&x) && (2.0..6.0).contains(&y)
+            })
+            .into_iter()
+            .map(|(particle, elevation)| (particle, elevation + 0.2))
+            .collect()
+        });
+    }
+
+    #[test]
+    fn lowering_matches_rebuild() {
+        assert_update_matches_rebuild(|elevation_map| {
+            within(elevation_map, |x, y| {
+                (5.0..7.0).contains(&x) && (4.0..5.0).contains(&y)
+            })
+            .into_iter()
+            .map(|(particle, elevation)| (particle, elevation - 0.3))
+            .collect()
+        });
+    }
+
+    #[test]
+    fn nudging_matches_rebuild() {
+        // Too small to reroute anything, but the tributaries next to the nudged particles
+        // get their streams rebuilt while the valley they join is left alone.
+        assert_update_matches_rebuild(|elevation_map| {
+            within(elevation_map, |x, y| {
+                (4.0..6.0).contains(&x) && (1.0..3.0).contains(&y)
+            })
+            .into_iter()
+            .map(|(particle, elevation)| (particle, elevation + 1e-9))
+            .collect()
+        });
+    }
+
+    #[test]
+    fn reversing_flow_matches_rebuild() {
+        assert_update_matches_rebuild(|elevation_map| {
+            within(elevation_map, |_, y| y < 5.0)
+                .into_iter()
+                .map(|(particle, elevation)| (particle, 1.0 - elevation))
+                .collect()
+        });
+    }
+
+    fn internal_nodes(drainage_map: &DrainageMap) -> HashMap<Particle, InternalNode> {
+        drainage_map
+            .map()
+            .iter()
+            .map(|(particle, node)| {
+                (
+                    *particle,
+                    InternalNode {
+                        elevation: node.elevation,
+                        area: node.area,
+                        flow_to: node.flow_to,
+                        slope: node.slope,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Accumulates one particle at a time, summing inflows in the order of their sites.
+    fn accumulate_serially(
+        nodes: &HashMap<Particle, InternalNode>,
+        runoff: &impl Fn(&Particle) -> f64,
+    ) -> HashMap<Particle, f64> {
+        fn visit(
+            particle: Particle,
+            nodes: &HashMap<Particle, InternalNode>,
+            inflows: &HashMap<Particle, Vec<Particle>>,
+            runoff: &impl Fn(&Particle) -> f64,
+            drainage_area: &mut HashMap<Particle, f64>,
+        ) -> f64 {
+            if let Some(area) = drainage_area.get(&particle) {
+                return *area;
+            }
+            let upstream_area = inflows
+                .get(&particle)
+                .into_iter()
+                .flatten()
+                .map(|upstream| visit(*upstream, nodes, inflows, runoff, drainage_area))
+                .sum::<f64>();
+            let area = nodes[&particle].area * runoff(&particle) + upstream_area;
+            drainage_area.insert(particle, area);
+            area
+        }
+
+        let mut inflows: HashMap<Particle, Vec<Particle>> = HashMap::new();
+        for (particle, node) in nodes.iter() {
+            if node.flow_to != *particle {
+                inflows.entry(node.flow_to).or_default().push(*particle);
+            }
+        }
+        for upstream in inflows.values_mut() {
+            upstream.sort_by(compare_sites);
+        }
+        let mut drainage_area = HashMap::new();
+        for particle in nodes.keys() {
+            visit(*particle, nodes, &inflows, runoff, &mut drainage_area);
+        }
+        drainage_area
+    }
+
+    /// Run with `--features parallel` to compare the parallel levels with the serial order.
+    #[test]
+    fn accumulation_matches_serial_order() {
+        let elevation_map = terrain(12.0, 12.0, |x, y| {
+            (x + y * 0.5 + (x * 0.9).sin() * (y * 1.1).cos()) * 0.01
+        });
+        let nodes = internal_nodes(&DrainageMapBuilder::new(&elevation_map).build().unwrap());
+        let runoff = |particle: &Particle| 1.0 + particle.site().0 * 0.1;
+
+        let expected = accumulate_serially(&nodes, &runoff);
+        let actual = accumulate_drainage_area(&nodes, runoff).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn plateaus_drain_and_conserve_area() {
+        // A flat-topped hill with a flat terrace running around its side.
+        let elevation_map = terrain(12.0, 12.0, |x, y| {
+            let distance = (x - 6.0).hypot(y - 6.0);
+            if distance < 3.0 {
+                1.0
+            } else if (4.0..5.0).contains(&distance) {
+                0.5
+            } else {
+                1.0 - distance * 0.1
+            }
+        });
+        let drainage_map = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        let nodes = drainage_map.map();
+
+        let total_area = nodes.iter().map(|(_, node)| node.area).sum::<f64>();
+        let drained_area = nodes
+            .iter()
+            .map(|(_, node)| node)
+            .filter(|node| node.is_outlet() || nodes.get(&node.flow_to).is_none())
+            .map(|node| node.drainage_area)
+            .sum::<f64>();
+        assert!((drained_area - total_area).abs() < 1e-9 * total_area);
+
+        for (_, node) in nodes.iter() {
+            if node.elevation == 1.0 || node.elevation == 0.5 {
+                assert!(!node.is_outlet());
+            }
+        }
+    }
+
+    #[test]
+    fn receiver_cycles_are_reported() {
+        let elevation_map = terrain(3.0, 1.0, |_, _| 0.0);
+        let particles = elevation_map
+            .iter()
+            .map(|(particle, _)| *particle)
+            .collect::<Vec<_>>();
+        let node = |flow_to: Particle| InternalNode {
+            elevation: 0.0,
+            area: 1.0,
+            flow_to,
+            slope: 0.0,
+        };
+        let mut nodes = HashMap::new();
+        nodes.insert(particles[0], node(particles[1]));
+        nodes.insert(particles[1], node(particles[0]));
+        nodes.insert(particles[2], node(particles[0]));
+
+        match accumulate_drainage_area(&nodes, |_| 1.0) {
+            Err(DrainageError::Cycle(cycles)) => {
+                assert_eq!(cycles.len(), 1);
+                let mut cycle = cycles[0].clone();
+                cycle.sort_by(compare_sites);
+                let mut expected = vec![particles[0], particles[1]];
+                expected.sort_by(compare_sites);
+                assert_eq!(cycle, expected);
+            }
+            other => panic!("expected a cycle, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn saving_the_same_map_gives_the_same_file() {
+        // A symmetric funnel: particles on its diagonals have two equally steep receivers,
+        // and equal rivers meet along its axes and at its center.
+        let elevation_map = terrain(9.0, 9.0, |x, y| ((x - 4.5).abs() + (y - 4.5).abs()) * 0.1);
+        let directory = std::env::temp_dir().join(format!("drainage-save-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let paths = ["first", "second"].map(|name| {
+            let path = directory.join(name).to_string_lossy().into_owned();
+            DrainageMapBuilder::new(&elevation_map)
+                .build()
+                .unwrap()
+                .save_to_file(&path);
+            path
+        });
+
+        let first = std::fs::read(&paths[0]).unwrap();
+        let second = std::fs::read(&paths[1]).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn updates_fill_depressions_again() {
+        // Closed edges fill from the lowest particle, so depressions are filled again over
+        // the whole map, while open edges only need it around the edits.
+        for (boundary_policy, epsilon) in [
+            (BoundaryPolicy::Closed, 1e-4),
+            (BoundaryPolicy::Open, 1e-4),
+            (BoundaryPolicy::Open, 0.0),
+        ] {
+            let elevation_map = terrain(10.0, 10.0, |x, y| (x * 2.0 + y) * 0.01);
+            let build = |elevation_map: &ParticleMap<f64>| {
+                DrainageMapBuilder::new(elevation_map)
+                    .boundary_policy(boundary_policy)
+                    .depression_handling(DepressionHandling::Fill { epsilon })
+                    .build()
+                    .unwrap()
+            };
+            let shifted =
+                |elevation_map: &ParticleMap<f64>, region: fn(f64, f64) -> bool, shift: f64| {
+                    within(elevation_map, region)
+                        .into_iter()
+                        .map(|(particle, elevation)| (particle, elevation + shift))
+                        .collect::<Vec<_>>()
+                };
+
+            // A pit is filled, drains again once a channel is dug out of it, and is filled
+            // again once the channel is dammed.
+            let mut elevation_map = elevation_map;
+            let mut updated = build(&elevation_map);
+            for changes in [
+                shifted(
+                    &elevation_map,
+                    |x, y| (4.0..6.0).contains(&x) && (4.0..6.0).contains(&y),
+                    -0.3,
+                ),
+                shifted(
+                    &elevation_map,
+                    |x, y| x < 4.0 && (4.0..5.0).contains(&y),
+                    -0.3,
+                ),
+                shifted(
+                    &elevation_map,
+                    |x, y| (2.0..3.0).contains(&x) && (4.0..5.0).contains(&y),
+                    0.6,
+                ),
+            ] {
+                elevation_map = edited(&elevation_map, &changes);
+                updated.update_elevations(&changes).unwrap();
+                let rebuilt = build(&elevation_map);
+                assert_same_drainage(&rebuilt, &updated);
+                assert_same_streams(&rebuilt, &updated);
+                for (particle, node) in rebuilt.map().iter() {
+                    assert_eq!(
+                        node.elevation,
+                        updated.map().get(particle).unwrap().elevation
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reaccumulated_cycles_are_reported() {
+        let particles = crate::test_util::particles(3.0, 1.0);
+        let receivers = HashMap::from([
+            (particles[0], particles[1]),
+            (particles[1], particles[0]),
+            (particles[2], particles[0]),
+        ]);
+        let inflows = receivers
+            .iter()
+            .map(|(particle, receiver)| (*receiver, *particle))
+            .fold(
+                HashMap::<Particle, Vec<Particle>>::new(),
+                |mut inflows, (receiver, particle)| {
+                    inflows.entry(receiver).or_default().push(particle);
+                    inflows
+                },
+            );
+
+        let result = reaccumulate_drainage_area(
+            &particles.iter().copied().collect(),
+            |particle| receivers[particle],
+            |particle| inflows_of(&inflows, particle),
+            |_| 1.0,
+            |_| 0.0,
+        );
+        match result {
+            Err(DrainageError::Cycle(cycles)) => {
+                assert_eq!(cycles.len(), 1);
+                let mut cycle = cycles[0].clone();
+                cycle.sort_by(compare_sites);
+                let mut expected = vec![particles[0], particles[1]];
+                expected.sort_by(compare_sites);
+                assert_eq!(cycle, expected);
+            }
+            other => panic!("expected a cycle, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn updates_keep_precipitation() {
+        assert_update_matches_rebuild_with(DepressionHandling::Keep, true, |elevation_map| {
+            within(elevation_map, |_, y| y < 5.0)
+                .into_iter()
+                .map(|(particle, elevation)| (particle, 1.0 - elevation))
+                .collect()
+        });
+    }
+
+    /// Map of nodes flowing along `receivers`, given as indices into the particles.
+    fn hand_built(receivers: &[usize]) -> (Vec<Particle>, DrainageMap) {
+        let particles = crate::test_util::particles(4.0, 3.0);
+        let particle_map = receivers
+            .iter()
+            .enumerate()
+            .map(|(index, receiver)| {
+                let node = DrainageBasinNode {
+                    particle: particles[index],
+                    flow_to: particles[*receiver],
+                    ..crate::test_util::node(1.0, 0.0)
+                };
+                (node.particle, node)
+            })
+            .collect::<ParticleMap<DrainageBasinNode>>();
+        let inflows = index_inflows(&particle_map);
+        let drainage_map = DrainageMap {
+            particle_map,
+            river_width_model: Box::new(PowerLawWidth::square_root(1.0)),
+            river_strength: 1.0,
+            river_ignoreable_width_strength: 0.01,
+            hydraulic_model: HydraulicModel::default(),
+            boundary_policy: BoundaryPolicy::default(),
+            routing_mode: RoutingMode::default(),
+            sea_level: None,
+            depression_handling: DepressionHandling::Keep,
+            unfilled_elevations: None,
+            inflows,
+            precipitation_map: None,
+            voronoi: VoronoiCache::default(),
+        };
+        (particles, drainage_map)
+    }
+
+    #[test]
+    fn stream_orders_grow_where_equal_orders_meet() {
+        // 0, 1 -> 2 and 3, 4 -> 5 are of order 2 and meet at the outlet 6, which the
+        // source 7 joins without raising its order.
+        let (particles, drainage_map) = hand_built(&[2, 2, 6, 5, 5, 6, 6, 6]);
+        let orders = drainage_map.stream_orders();
+        let order = |index: usize| orders[&particles[index]];
+        assert_eq!([0, 1, 3, 4, 7].map(order), [1; 5]);
+        assert_eq!([2, 5].map(order), [2; 2]);
+        assert_eq!(order(6), 3);
+
+        // A single order 2 tributary keeps the order of the trunk it joins.
+        let (particles, drainage_map) = hand_built(&[2, 2, 3, 3]);
+        let orders = drainage_map.stream_orders();
+        assert_eq!(orders[&particles[3]], 2);
+    }
+
+    #[test]
+    fn outlets_stay_in_the_map_as_points() {
+        let elevation_map = terrain(8.0, 8.0, |x, y| ((x - 4.0).powi(2) + y) * 0.01);
+        let drainage_map = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        assert_eq!(
+            drainage_map.map().iter().count(),
+            elevation_map.iter().count()
+        );
+        assert!(drainage_map.outlets().count() > 0);
+        for outlet in drainage_map.outlets() {
+            assert_eq!(outlet.main_river, Stream::Point(outlet.particle.site()));
+        }
+    }
+
+    /// Asserts that every tributary ends on the middle of a channel of the trunk reach it
+    /// joins, and returns the number of tributaries.
+    fn assert_tributaries_joined(drainage_map: &DrainageMap) -> usize {
+        let trunks = drainage_map.trunks();
+        let mut joined = 0;
+        for (particle, node) in drainage_map.map().iter() {
+            let receiver = drainage_map.map().get(&node.flow_to).unwrap();
+            let trunk = trunks.get(&node.flow_to).copied();
+            if node.is_outlet() || receiver.is_outlet() || trunk == Some(*particle) {
+                continue;
+            }
+            let trunk = &drainage_map.map().get(&trunk.unwrap()).unwrap().main_river;
+            let end = node.main_river.evaluate(1.0);
+            let distance = (0..trunk.channel_num())
+                .map(|channel| {
+                    let point = trunk.evaluate_channel(channel, 0.5);
+                    (point.0 - end.0).hypot(point.1 - end.1)
+                })
+                .fold(f64::MAX, f64::min);
+            assert!(distance < 1e-9, "{distance}");
+            joined += 1;
+        }
+        joined
+    }
+
+    #[test]
+    fn tributaries_stay_joined_to_meandering_and_braided_reaches() {
+        let elevation_map = terrain(8.0, 5.0, |x, y| x * 0.02 + (y - 2.5).abs() * 0.03);
+        let mut drainage_map = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        assert!(assert_tributaries_joined(&drainage_map) > 0);
+
+        drainage_map.apply_meanders(&MeanderParams {
+            slope_threshold: 1.0,
+            ..Default::default()
+        });
+        assert!(drainage_map
+            .map()
+            .iter()
+            .any(|(_, node)| matches!(node.main_river, Stream::Meandering { .. })));
+        assert_tributaries_joined(&drainage_map);
+
+        // No channel of an even braid runs through the middle of the reach.
+        drainage_map.apply_braiding(&BraidParams {
+            slope_threshold: 1.0,
+            minimum_discharge: 0.0,
+            channels: 2,
+            spread: 1.0,
+        });
+        assert!(drainage_map
+            .map()
+            .iter()
+            .any(|(_, node)| node.main_river.channel_num() == 2));
+        assert_tributaries_joined(&drainage_map);
+    }
+}
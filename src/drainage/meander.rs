@@ -0,0 +1,152 @@
+use worley_particle::Particle;
+
+use super::node::DrainageBasinNode;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeanderParams {
+    /// Reaches steeper than this (absolute slope) are left straight.
+    pub slope_threshold: f64,
+    /// Largest sideways bend of a reach, relative to the river width.
+    pub amplitude: f64,
+    /// Ratio of the meandering to the straight reach length that flat reaches aim for, as
+    /// far as `amplitude` allows. `1.0` disables meandering.
+    pub sinuosity: f64,
+    pub seed: u64,
+}
+
+impl Default for MeanderParams {
+    fn default() -> Self {
+        Self {
+            slope_threshold: 0.05,
+            amplitude: 2.0,
+            sinuosity: 1.5,
+            seed: 0,
+        }
+    }
+}
+
+impl MeanderParams {
+    /// Signed bend giving the reach of `node` its target sinuosity, which grows from
+    /// `1.0` at the slope threshold to `sinuosity` on flat ground.
+    pub(crate) fn amplitude_of(&self, node: &DrainageBasinNode, river_width: f64) -> f64 {
+        let slope = node.slope.abs();
+        if slope >= self.slope_threshold || self.sinuosity <= 1.0 {
+            return 0.0;
+        }
+        let flatness = 1.0 - slope / self.slope_threshold;
+        let noise = hash_to_unit(self.seed, &node.particle) * 2.0 - 1.0;
+        let side = if noise < 0.0 { -1.0 } else { 1.0 };
+        let strength = 0.5 + noise.abs() * 0.5;
+        let target = 1.0 + (self.sinuosity - 1.0) * flatness * strength;
+
+        let straight = node.main_river.meander(0.0);
+        let straight_length = straight.length();
+        let limit = self.amplitude * river_width;
+        if straight_length == 0.0 || limit <= 0.0 {
+            return 0.0;
+        }
+        // Bending a curved reach towards its inside first shortens it, so the length is
+        // not monotonic in the amplitude; bisecting still finds an amplitude on the target.
+        let sinuosity =
+            |amplitude: f64| straight.meander(side * amplitude).length() / straight_length;
+        if sinuosity(limit) <= target {
+            return side * limit;
+        }
+
+        let (mut low, mut high) = (0.0, limit);
+        for _ in 0..32 {
+            let middle = (low + high) / 2.0;
+            if sinuosity(middle) < target {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        side * (low + high) / 2.0
+    }
+}
+
+fn hash_to_unit(seed: u64, particle: &Particle) -> f64 {
+    let (x, y) = particle.site();
+    let mut z = seed ^ x.to_bits().rotate_left(17) ^ y.to_bits().rotate_left(41);
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{drainage::node::Stream, test_util};
+
+    fn node(slope: f64) -> DrainageBasinNode {
+        test_util::node(1.0, slope)
+    }
+
+    fn curved_node() -> DrainageBasinNode {
+        DrainageBasinNode {
+            main_river: Stream::new((0.0, 0.0), (1.0, 0.0), (1.0, 1.0)),
+            ..node(0.0)
+        }
+    }
+
+    #[test]
+    fn flat_reaches_reach_the_target_sinuosity() {
+        let params = MeanderParams {
+            sinuosity: 1.2,
+            ..Default::default()
+        };
+        let node = node(0.0);
+        let noise = hash_to_unit(params.seed, &node.particle) * 2.0 - 1.0;
+        let target = 1.0 + 0.2 * (0.5 + noise.abs() * 0.5);
+
+        let amplitude = params.amplitude_of(&node, 1.0);
+        let sinuosity = node.main_river.meander(amplitude).length() / node.main_river.length();
+        assert!((sinuosity - target).abs() < 1e-6);
+        assert_eq!(amplitude < 0.0, noise < 0.0);
+    }
+
+    #[test]
+    fn curved_reaches_reach_the_target_sinuosity_on_both_sides() {
+        let mut sides = Vec::new();
+        for seed in 0..16 {
+            let params = MeanderParams {
+                sinuosity: 1.2,
+                seed,
+                ..Default::default()
+            };
+            let node = curved_node();
+            let noise = hash_to_unit(seed, &node.particle) * 2.0 - 1.0;
+            let target = 1.0 + 0.2 * (0.5 + noise.abs() * 0.5);
+
+            let amplitude = params.amplitude_of(&node, 1.0);
+            let sinuosity = node.main_river.meander(amplitude).length() / node.main_river.length();
+            assert!((sinuosity - target).abs() < 1e-6);
+            sides.push(amplitude < 0.0);
+        }
+        assert!(sides.contains(&true) && sides.contains(&false));
+    }
+
+    #[test]
+    fn amplitude_caps_the_bend() {
+        let params = MeanderParams {
+            amplitude: 0.01,
+            sinuosity: 3.0,
+            ..Default::default()
+        };
+        assert_eq!(params.amplitude_of(&node(0.0), 2.0).abs(), 0.02);
+    }
+
+    #[test]
+    fn steep_reaches_stay_straight() {
+        let params = MeanderParams::default();
+        assert_eq!(params.amplitude_of(&node(params.slope_threshold), 1.0), 0.0);
+        let params = MeanderParams {
+            sinuosity: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(params.amplitude_of(&node(0.0), 1.0), 0.0);
+    }
+}
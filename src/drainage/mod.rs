@@ -1,2 +1,12 @@
+pub mod braid;
+pub mod builder;
+pub mod chunk;
+pub mod depression;
+pub mod error;
+mod flat;
+pub mod hydraulics;
 pub mod map;
+pub mod meander;
 pub mod node;
+pub mod routing;
+pub mod width;
@@ -4,6 +4,10 @@ use bezier_rs::{Bezier, BezierHandles, TValue};
 use glam::DVec2;
 use worley_particle::{map::rw::ParticleMapAttributeRW, Particle};
 
+use crate::geometry::closest_on_segment;
+
+use super::width::{PowerLawWidth, RiverWidthModel};
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct DrainageBasinInput {
     pub elevation: f64,
@@ -13,26 +17,100 @@ pub struct DrainageBasinInput {
 pub enum Stream {
     Path(Bezier),
     Point((f64, f64)),
+    /// `path` bent `amplitude` to its left at the middle. The bend fades out towards both
+    /// ends, so the reach keeps the end points and tangents of `path`.
+    Meandering {
+        path: Bezier,
+        amplitude: f64,
+    },
+    /// Several parallel sub-channels spread around `path`, rejoining at both ends, and bent
+    /// by `meander` like a meandering path.
+    Braided {
+        path: Bezier,
+        channels: usize,
+        spread: f64,
+        meander: f64,
+    },
+}
+
+fn path_from_strs(s: &[&str]) -> Result<Bezier, Box<dyn std::error::Error>> {
+    let start = DVec2 {
+        x: s[0].parse::<f64>()?,
+        y: s[1].parse::<f64>()?,
+    };
+    let handle_start = DVec2 {
+        x: s[2].parse::<f64>()?,
+        y: s[3].parse::<f64>()?,
+    };
+    let end = DVec2 {
+        x: s[6].parse::<f64>()?,
+        y: s[7].parse::<f64>()?,
+    };
+    if s[4].is_empty() {
+        return Ok(Bezier::from_quadratic_coordinates(
+            start.x,
+            start.y,
+            handle_start.x,
+            handle_start.y,
+            end.x,
+            end.y,
+        ));
+    }
+    let handle_end = DVec2 {
+        x: s[4].parse::<f64>()?,
+        y: s[5].parse::<f64>()?,
+    };
+    Ok(Bezier::from_cubic_coordinates(
+        start.x,
+        start.y,
+        handle_start.x,
+        handle_start.y,
+        handle_end.x,
+        handle_end.y,
+        end.x,
+        end.y,
+    ))
+}
+
+fn path_to_strings(path: &Bezier) -> Vec<String> {
+    let (handle_start, handle_end) = match path.handles {
+        BezierHandles::Quadratic { handle } => (handle, None),
+        BezierHandles::Cubic {
+            handle_start,
+            handle_end,
+        } => (handle_start, Some(handle_end)),
+        _ => unreachable!(),
+    };
+    vec![
+        path.start.x.to_string(),
+        path.start.y.to_string(),
+        handle_start.x.to_string(),
+        handle_start.y.to_string(),
+        handle_end.map_or("".to_string(), |h| h.x.to_string()),
+        handle_end.map_or("".to_string(), |h| h.y.to_string()),
+        path.end.x.to_string(),
+        path.end.y.to_string(),
+    ]
 }
 
+/// Columns: kind, path start, handles and end, channel count, channel spread and meander
+/// amplitude. Unused columns are empty.
 impl ParticleMapAttributeRW for Stream {
     fn from_strs(s: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
         if s[0] == "Path" {
-            let start = DVec2 {
-                x: s[1].parse::<f64>()?,
-                y: s[2].parse::<f64>()?,
-            };
-            let handle = DVec2 {
-                x: s[3].parse::<f64>()?,
-                y: s[4].parse::<f64>()?,
-            };
-            let end = DVec2 {
-                x: s[5].parse::<f64>()?,
-                y: s[6].parse::<f64>()?,
-            };
-            Ok(Stream::Path(Bezier::from_quadratic_coordinates(
-                start.x, start.y, handle.x, handle.y, end.x, end.y,
-            )))
+            Ok(Stream::Path(path_from_strs(&s[1..9])?))
+        } else if s[0] == "Meandering" {
+            Ok(Stream::Meandering {
+                path: path_from_strs(&s[1..9])?,
+                amplitude: s[11].parse::<f64>()?,
+            })
+        } else if s[0] == "Braided" {
+            Ok(Stream::Braided {
+                path: path_from_strs(&s[1..9])?,
+                channels: s[9].parse::<usize>()?,
+                spread: s[10].parse::<f64>()?,
+                meander: s[11].parse::<f64>()?,
+            })
         } else {
             let x = s[1].parse::<f64>()?;
             let y = s[2].parse::<f64>()?;
@@ -42,21 +120,27 @@ impl ParticleMapAttributeRW for Stream {
 
     fn to_strings(&self) -> Vec<String> {
         match self {
-            Stream::Path(path) => {
-                let handle = match path.handles {
-                    BezierHandles::Quadratic { handle } => handle,
-                    _ => unreachable!(),
-                };
-                vec![
-                    "Path".to_string(),
-                    path.start.x.to_string(),
-                    path.start.y.to_string(),
-                    handle.x.to_string(),
-                    handle.y.to_string(),
-                    path.end.x.to_string(),
-                    path.end.y.to_string(),
-                ]
-            }
+            Stream::Path(path) => std::iter::once("Path".to_string())
+                .chain(path_to_strings(path))
+                .chain(vec!["".to_string(), "".to_string(), "".to_string()])
+                .collect(),
+            Stream::Meandering { path, amplitude } => std::iter::once("Meandering".to_string())
+                .chain(path_to_strings(path))
+                .chain(vec!["".to_string(), "".to_string(), amplitude.to_string()])
+                .collect(),
+            Stream::Braided {
+                path,
+                channels,
+                spread,
+                meander,
+            } => std::iter::once("Braided".to_string())
+                .chain(path_to_strings(path))
+                .chain(vec![
+                    channels.to_string(),
+                    spread.to_string(),
+                    meander.to_string(),
+                ])
+                .collect(),
             Stream::Point((x, y)) => vec![
                 "Point".to_string(),
                 x.to_string(),
@@ -65,44 +149,249 @@ impl ParticleMapAttributeRW for Stream {
                 "".to_string(),
                 "".to_string(),
                 "".to_string(),
+                "".to_string(),
+                "".to_string(),
+                "".to_string(),
+                "".to_string(),
+                "".to_string(),
             ],
         }
     }
 
     fn len_strs() -> usize {
-        7
+        12
+    }
+}
+
+fn path_normal(path: &Bezier, t: f64) -> DVec2 {
+    let (t0, t1) = ((t - 1e-3).max(0.0), (t + 1e-3).min(1.0));
+    let tangent = path.evaluate(TValue::Parametric(t1)) - path.evaluate(TValue::Parametric(t0));
+    let length = tangent.x.hypot(tangent.y);
+    if length == 0.0 {
+        return DVec2 { x: 0.0, y: 0.0 };
+    }
+    DVec2 {
+        x: -tangent.y / length,
+        y: tangent.x / length,
     }
 }
 
 impl Stream {
     pub fn new(site_0: (f64, f64), site_1: (f64, f64), site_2: (f64, f64)) -> Self {
-        let path_start = ((site_0.0 + site_1.0) / 2.0, (site_0.1 + site_1.1) / 2.0);
         if site_0 == site_1 {
             return Stream::Point(site_0);
         }
-        let path_end = ((site_1.0 + site_2.0) / 2.0, (site_1.1 + site_2.1) / 2.0);
-        let path = Bezier::from_quadratic_coordinates(
-            path_start.0,
-            path_start.1,
-            site_1.0,
-            site_1.1,
-            path_end.0,
-            path_end.1,
+        Self::quadratic(midpoint(site_0, site_1), site_1, midpoint(site_1, site_2))
+    }
+
+    /// Reach of a river source: starts at the source site itself instead of the midpoint
+    /// towards its receiver.
+    pub fn from_source(site_0: (f64, f64), site_1: (f64, f64), site_2: (f64, f64)) -> Self {
+        if site_0 == site_1 {
+            return Stream::Point(site_0);
+        }
+        Self::quadratic(site_0, site_1, midpoint(site_1, site_2))
+    }
+
+    /// Reach of a tributary joining the trunk reach that starts at `trunk_start` and bends
+    /// around `site_1` towards `site_2`. The path ends on the middle of the trunk curve,
+    /// parallel to it.
+    pub fn tributary(
+        start: (f64, f64),
+        site_1: (f64, f64),
+        site_2: (f64, f64),
+        trunk_start: (f64, f64),
+    ) -> Self {
+        let trunk_end = midpoint(site_1, site_2);
+        let junction = midpoint(midpoint(trunk_start, site_1), midpoint(site_1, trunk_end));
+        let direction = (trunk_end.0 - trunk_start.0, trunk_end.1 - trunk_start.1);
+        joining_path(start, junction, direction)
+            .map(Stream::Path)
+            .unwrap_or_else(|| Self::quadratic(start, site_1, trunk_end))
+    }
+
+    /// Where a tributary coming from `towards` joins the middle of this reach: the point
+    /// on the closest channel, and the direction the channel runs there.
+    pub(crate) fn junction(&self, towards: (f64, f64)) -> ((f64, f64), (f64, f64)) {
+        let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+        let channel = (0..self.channel_num())
+            .min_by(|a, b| {
+                distance(self.evaluate_channel(*a, 0.5), towards)
+                    .total_cmp(&distance(self.evaluate_channel(*b, 0.5), towards))
+            })
+            .unwrap_or(0);
+        let (before, after) = (
+            self.evaluate_channel(channel, 0.5 - 1e-3),
+            self.evaluate_channel(channel, 0.5 + 1e-3),
         );
+        (
+            self.evaluate_channel(channel, 0.5),
+            (after.0 - before.0, after.1 - before.1),
+        )
+    }
 
-        Stream::Path(path)
+    /// Moves the end of a tributary reach to `junction`, arriving along `direction`. The
+    /// start, meander and braid of the reach are kept.
+    pub(crate) fn rejoin(&self, junction: (f64, f64), direction: (f64, f64)) -> Self {
+        let (Stream::Path(path) | Stream::Meandering { path, .. } | Stream::Braided { path, .. }) =
+            self
+        else {
+            return self.clone();
+        };
+        let start = path.evaluate(TValue::Parametric(0.0));
+        let Some(joined) = joining_path((start.x, start.y), junction, direction) else {
+            return self.clone();
+        };
+        match self {
+            Stream::Meandering { amplitude, .. } => Stream::Meandering {
+                path: joined,
+                amplitude: *amplitude,
+            },
+            Stream::Braided {
+                channels,
+                spread,
+                meander,
+                ..
+            } => Stream::Braided {
+                path: joined,
+                channels: *channels,
+                spread: *spread,
+                meander: *meander,
+            },
+            _ => Stream::Path(joined),
+        }
+    }
+
+    fn quadratic(start: (f64, f64), handle: (f64, f64), end: (f64, f64)) -> Self {
+        Stream::Path(Bezier::from_quadratic_coordinates(
+            start.0, start.1, handle.0, handle.1, end.0, end.1,
+        ))
+    }
+
+    /// Bends the reach `amplitude` to the left of its path, or to the right if negative.
+    /// The bend is largest at the middle and fades out towards both ends, which keep their
+    /// points and tangents. A meandering reach is bent anew rather than further.
+    pub fn meander(&self, amplitude: f64) -> Self {
+        match self {
+            Stream::Path(path) | Stream::Meandering { path, .. } => Stream::Meandering {
+                path: *path,
+                amplitude,
+            },
+            Stream::Braided {
+                path,
+                channels,
+                spread,
+                ..
+            } => Stream::Braided {
+                path: *path,
+                channels: *channels,
+                spread: *spread,
+                meander: amplitude,
+            },
+            Stream::Point(_) => self.clone(),
+        }
+    }
+
+    /// Splits a path into `channels` sub-channels placed `spread` apart at the middle of
+    /// the reach.
+    pub fn braid(&self, channels: usize, spread: f64) -> Self {
+        if channels <= 1 {
+            return self.clone();
+        }
+        match self {
+            Stream::Path(path) | Stream::Meandering { path, .. } | Stream::Braided { path, .. } => {
+                Stream::Braided {
+                    path: *path,
+                    channels,
+                    spread,
+                    meander: self.meander_amplitude(),
+                }
+            }
+            Stream::Point(_) => self.clone(),
+        }
+    }
+
+    pub fn channel_num(&self) -> usize {
+        match self {
+            Stream::Braided { channels, .. } => *channels,
+            _ => 1,
+        }
+    }
+
+    fn meander_amplitude(&self) -> f64 {
+        match self {
+            Stream::Meandering { amplitude, .. } => *amplitude,
+            Stream::Braided { meander, .. } => *meander,
+            _ => 0.0,
+        }
+    }
+
+    /// Distance of a channel to the left of the path at `t`.
+    fn lateral_offset(&self, channel: usize, t: f64) -> f64 {
+        let bend = (std::f64::consts::PI * t).sin();
+        let meander = self.meander_amplitude() * bend.powi(2);
+        match self {
+            Stream::Braided {
+                channels, spread, ..
+            } => channel_offset(channel, *channels, *spread) * bend + meander,
+            _ => meander,
+        }
     }
 
     pub fn evaluate(&self, t: f64) -> (f64, f64) {
+        self.evaluate_offset(
+            t,
+            self.meander_amplitude() * (std::f64::consts::PI * t).sin().powi(2),
+        )
+    }
+
+    pub fn evaluate_channel(&self, channel: usize, t: f64) -> (f64, f64) {
+        self.evaluate_offset(t, self.lateral_offset(channel, t))
+    }
+
+    fn evaluate_offset(&self, t: f64, offset: f64) -> (f64, f64) {
         match self {
-            Stream::Path(path) => {
-                let point = path.evaluate(TValue::Parametric(t));
+            Stream::Path(path) | Stream::Meandering { path, .. } | Stream::Braided { path, .. } => {
+                let mut point = path.evaluate(TValue::Parametric(t));
+                if offset != 0.0 {
+                    point = point + path_normal(path, t) * offset;
+                }
                 (point.x, point.y)
             }
             Stream::Point((x, y)) => (*x, *y),
         }
     }
 
+    /// Length of the centerline, approximated by a polyline.
+    pub fn length(&self) -> f64 {
+        const SEGMENTS: usize = 32;
+        (0..SEGMENTS)
+            .map(|i| {
+                let a = self.evaluate(i as f64 / SEGMENTS as f64);
+                let b = self.evaluate((i + 1) as f64 / SEGMENTS as f64);
+                (a.0 - b.0).hypot(a.1 - b.1)
+            })
+            .sum()
+    }
+
+    /// Point of the segment from `from` to `to` closest to a channel passing within
+    /// `width` of it, if any. Channels are approximated by polylines.
+    pub fn crossing(&self, from: (f64, f64), to: (f64, f64), width: f64) -> Option<(f64, f64)> {
+        const SEGMENTS: usize = 32;
+        let channel_width = width / self.channel_num() as f64;
+        (0..self.channel_num())
+            .flat_map(|channel| {
+                (0..SEGMENTS).map(move |i| {
+                    let a = self.evaluate_channel(channel, i as f64 / SEGMENTS as f64);
+                    let b = self.evaluate_channel(channel, (i + 1) as f64 / SEGMENTS as f64);
+                    closest_approach(from, to, a, b)
+                })
+            })
+            .filter(|(distance, _)| *distance < channel_width)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, point)| point)
+    }
+
     pub fn collides(&self, x: f64, y: f64, width: f64) -> bool {
         match self {
             Stream::Path(path) => {
@@ -112,6 +401,21 @@ impl Stream {
                     (projection_point.x - x).powi(2) + (projection_point.y - y).powi(2);
                 squared_distance < width.powi(2)
             }
+            Stream::Meandering { path, .. } | Stream::Braided { path, .. } => {
+                let projection = path.project(DVec2 { x, y }, None);
+                let projection_point = path.evaluate(TValue::Parametric(projection));
+                let normal = path_normal(path, projection);
+                let lateral =
+                    (x - projection_point.x) * normal.x + (y - projection_point.y) * normal.y;
+                let along_squared = (projection_point.x - x).powi(2)
+                    + (projection_point.y - y).powi(2)
+                    - lateral.powi(2);
+                let channel_width = width / self.channel_num() as f64;
+                (0..self.channel_num()).any(|channel| {
+                    let offset = self.lateral_offset(channel, projection);
+                    along_squared.max(0.0) + (lateral - offset).powi(2) < channel_width.powi(2)
+                })
+            }
             Stream::Point((x0, y0)) => {
                 let squared_distance = (x0 - x).powi(2) + (y0 - y).powi(2);
                 squared_distance < width.powi(2)
@@ -120,9 +424,64 @@ impl Stream {
     }
 }
 
+/// Quadratic path from `start` to `junction`, ending parallel to `direction`.
+fn joining_path(start: (f64, f64), junction: (f64, f64), direction: (f64, f64)) -> Option<Bezier> {
+    let direction_length = direction.0.hypot(direction.1);
+    if direction_length == 0.0 || start == junction {
+        return None;
+    }
+    let handle_length = (junction.0 - start.0).hypot(junction.1 - start.1) / 2.0;
+    let handle = (
+        junction.0 - direction.0 / direction_length * handle_length,
+        junction.1 - direction.1 / direction_length * handle_length,
+    );
+    Some(Bezier::from_quadratic_coordinates(
+        start.0, start.1, handle.0, handle.1, junction.0, junction.1,
+    ))
+}
+
+pub(crate) fn midpoint(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)
+}
+
+/// Distance between the segments `p0`-`p1` and `q0`-`q1`, with the point of the first
+/// segment where it is reached.
+fn closest_approach(
+    p0: (f64, f64),
+    p1: (f64, f64),
+    q0: (f64, f64),
+    q1: (f64, f64),
+) -> (f64, (f64, f64)) {
+    let cross = |o: (f64, f64), a: (f64, f64), b: (f64, f64)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    let (d0, d1) = (cross(q0, q1, p0), cross(q0, q1, p1));
+    let (d2, d3) = (cross(p0, p1, q0), cross(p0, p1, q1));
+    if d0 * d1 < 0.0 && d2 * d3 < 0.0 {
+        let t = d0 / (d0 - d1);
+        return (0.0, (p0.0 + t * (p1.0 - p0.0), p0.1 + t * (p1.1 - p0.1)));
+    }
+    let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+    [
+        (q0, closest_on_segment(q0, p0, p1)),
+        (q1, closest_on_segment(q1, p0, p1)),
+        (closest_on_segment(p0, q0, q1), p0),
+        (closest_on_segment(p1, q0, q1), p1),
+    ]
+    .into_iter()
+    .map(|(on_q, on_p)| (distance(on_q, on_p), on_p))
+    .min_by(|a, b| a.0.total_cmp(&b.0))
+    .unwrap()
+}
+
+fn channel_offset(channel: usize, channels: usize, spread: f64) -> f64 {
+    (channel as f64 - (channels as f64 - 1.0) / 2.0) * spread
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct DrainageBasinNode {
     pub particle: Particle,
+    pub elevation: f64,
     pub area: f64,
     pub drainage_area: f64,
     pub slope: f64,
@@ -130,6 +489,7 @@ pub struct DrainageBasinNode {
     pub main_river: Stream,
 }
 
+/// Columns: particle, receiver, stream, area, drainage area, slope and elevation.
 impl ParticleMapAttributeRW for DrainageBasinNode {
     fn from_strs(s: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
         let particle = Particle::from_strs(&s[..Particle::len_strs()])?;
@@ -140,9 +500,11 @@ impl ParticleMapAttributeRW for DrainageBasinNode {
         let area = s[Particle::len_strs() * 2 + Stream::len_strs()].parse::<f64>()?;
         let drainage_area = s[Particle::len_strs() * 2 + Stream::len_strs() + 1].parse::<f64>()?;
         let slope = s[Particle::len_strs() * 2 + Stream::len_strs() + 2].parse::<f64>()?;
+        let elevation = s[Particle::len_strs() * 2 + Stream::len_strs() + 3].parse::<f64>()?;
 
         Ok(DrainageBasinNode {
             particle,
+            elevation,
             area,
             drainage_area,
             slope,
@@ -159,6 +521,7 @@ impl ParticleMapAttributeRW for DrainageBasinNode {
             self.area.to_string(),
             self.drainage_area.to_string(),
             self.slope.to_string(),
+            self.elevation.to_string(),
         ];
 
         particle
@@ -170,11 +533,15 @@ impl ParticleMapAttributeRW for DrainageBasinNode {
     }
 
     fn len_strs() -> usize {
-        Particle::len_strs() + Particle::len_strs() + Stream::len_strs() + 3
+        Particle::len_strs() + Particle::len_strs() + Stream::len_strs() + 4
     }
 }
 
 impl DrainageBasinNode {
+    pub fn is_outlet(&self) -> bool {
+        self.flow_to == self.particle
+    }
+
     pub fn direction(&self) -> f64 {
         let site_0 = self.particle.site();
         let site_1 = self.flow_to.site();
@@ -182,6 +549,145 @@ impl DrainageBasinNode {
     }
 
     pub fn river_width(&self, strength: f64) -> f64 {
-        self.drainage_area.sqrt() * strength * self.particle.params().scale
+        PowerLawWidth::square_root(strength).width(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: ParticleMapAttributeRW>(value: &T) -> T {
+        let strings = value.to_strings();
+        assert_eq!(strings.len(), T::len_strs());
+        T::from_strs(&strings.iter().map(String::as_str).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+        (a.0 - b.0).hypot(a.1 - b.1)
+    }
+
+    fn tangent(stream: &Stream, t: f64) -> (f64, f64) {
+        let (a, b) = (stream.evaluate(t), stream.evaluate(t + 1e-6));
+        let length = distance(a, b);
+        ((b.0 - a.0) / length, (b.1 - a.1) / length)
+    }
+
+    #[test]
+    fn segments_cross_streams_anywhere_along_them() {
+        let stream = Stream::new((0.0, 0.0), (2.0, 0.0), (4.0, 0.0));
+        // Crossing near its end, far from the middle of the segment.
+        let crossing = stream.crossing((1.5, -0.1), (1.5, 2.0), 0.01).unwrap();
+        assert!(distance(crossing, (1.5, 0.0)) < 1e-9);
+        // Passing by within the width without crossing the centerline.
+        let near = stream.crossing((1.0, 0.05), (1.0, 1.0), 0.1).unwrap();
+        assert!(distance(near, (1.0, 0.05)) < 1e-9);
+        assert_eq!(stream.crossing((1.0, 0.2), (1.0, 1.0), 0.1), None);
+        // Beyond the end of the reach.
+        assert_eq!(stream.crossing((3.5, -1.0), (3.5, 1.0), 0.1), None);
+
+        let braided = stream.braid(2, 0.4);
+        assert!(braided.crossing((2.0, -1.0), (2.0, 1.0), 0.1).is_some());
+        assert_eq!(braided.crossing((2.0, 0.05), (2.0, 0.1), 0.1), None);
+    }
+
+    #[test]
+    fn meander_keeps_end_points_and_tangents() {
+        let stream = Stream::new((0.0, 0.0), (1.0, 0.0), (1.0, 1.0));
+        for meandered in [stream.meander(0.2), stream.braid(3, 0.1).meander(-0.2)] {
+            assert_eq!(meandered.evaluate(0.0), stream.evaluate(0.0));
+            assert_eq!(meandered.evaluate(1.0), stream.evaluate(1.0));
+            for t in [0.0, 1.0 - 1e-6] {
+                assert!(distance(tangent(&meandered, t), tangent(&stream, t)) < 1e-3);
+            }
+            assert!((distance(meandered.evaluate(0.5), stream.evaluate(0.5)) - 0.2).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn meander_bends_anew() {
+        let stream = Stream::new((0.0, 0.0), (1.0, 0.0), (2.0, 0.0));
+        assert_eq!(stream.meander(0.3).meander(0.1), stream.meander(0.1));
+        assert_eq!(stream.meander(0.3).meander(0.0).length(), stream.length());
+    }
+
+    #[test]
+    fn meandering_stream_collides_along_its_bend() {
+        let stream = Stream::new((0.0, 0.0), (1.0, 0.0), (2.0, 0.0)).meander(0.2);
+        let (x, y) = stream.evaluate(0.5);
+        assert!(stream.collides(x, y, 0.05));
+        assert!(!stream.collides(1.0, 0.0, 0.05));
+    }
+
+    #[test]
+    fn streams_round_trip_through_strings() {
+        let path = Stream::new((0.0, 0.0), (1.0, 0.5), (2.0, 0.25));
+        for stream in [
+            path.clone(),
+            path.meander(0.125),
+            path.braid(3, 0.5),
+            path.braid(2, 0.5).meander(-0.25),
+            Stream::Point((0.5, 1.5)),
+        ] {
+            assert_eq!(round_trip(&stream), stream);
+        }
+    }
+
+    #[test]
+    fn nodes_round_trip_through_strings() {
+        let particles = crate::test_util::particles(2.0, 1.0);
+        let node = DrainageBasinNode {
+            particle: particles[0],
+            elevation: 0.5,
+            area: 1.0,
+            drainage_area: 3.5,
+            slope: 0.25,
+            flow_to: particles[1],
+            main_river: Stream::new(particles[0].site(), particles[1].site(), (3.0, 0.0))
+                .meander(0.125),
+        };
+        assert_eq!(round_trip(&node), node);
+    }
+
+    #[test]
+    fn braided_channels_spread_around_the_reach() {
+        let stream = Stream::new((0.0, 0.0), (1.0, 0.0), (2.0, 0.0)).braid(3, 0.5);
+        for channel in 0..3 {
+            for t in [0.0, 1.0] {
+                assert!(distance(stream.evaluate_channel(channel, t), stream.evaluate(t)) < 1e-9);
+            }
+        }
+        let middle = stream.evaluate(0.5);
+        let offsets = (0..3)
+            .map(|channel| stream.evaluate_channel(channel, 0.5).1 - middle.1)
+            .collect::<Vec<_>>();
+        assert!((offsets[0].abs() - 0.5).abs() < 1e-9);
+        assert!(offsets[1].abs() < 1e-9);
+        assert!((offsets[0] + offsets[2]).abs() < 1e-9);
+        assert_eq!(stream.braid(1, 0.5), stream);
+    }
+
+    #[test]
+    fn sources_start_at_their_site() {
+        let source = Stream::from_source((0.0, 0.0), (1.0, 0.0), (2.0, 0.0));
+        assert_eq!(source.evaluate(0.0), (0.0, 0.0));
+        assert_eq!(source.evaluate(1.0), (1.5, 0.0));
+        let reach = Stream::new((0.0, 0.0), (1.0, 0.0), (2.0, 0.0));
+        assert_eq!(reach.evaluate(0.0), (0.5, 0.0));
+        assert_eq!(
+            Stream::new((1.0, 1.0), (1.0, 1.0), (2.0, 0.0)),
+            Stream::Point((1.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn tributaries_join_the_trunk_along_it() {
+        // The trunk comes from the left and bends around (1, 0) towards (2, 0).
+        let trunk_start = (0.5, 0.0);
+        let tributary = Stream::tributary((1.0, 1.0), (1.0, 0.0), (2.0, 0.0), trunk_start);
+        let trunk = Stream::quadratic(trunk_start, (1.0, 0.0), (1.5, 0.0));
+        let junction = tributary.evaluate(1.0);
+        assert!(distance(junction, trunk.evaluate(0.5)) < 1e-9);
+        assert!(distance(tangent(&tributary, 1.0 - 1e-6), tangent(&trunk, 0.5)) < 1e-3);
     }
 }
@@ -0,0 +1,34 @@
+/// How the receiver of a particle is chosen among its lower neighbors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoutingMode {
+    /// The neighbor with the largest drop per distance.
+    #[default]
+    SteepestDescent,
+    /// The neighbor with the lowest elevation, whatever its distance.
+    LowestNeighbor,
+}
+
+impl RoutingMode {
+    /// Lower is a better receiver. `drop` is the elevation difference to the neighbor,
+    /// negative when the neighbor is lower.
+    pub(crate) fn score(&self, drop: f64, distance: f64) -> f64 {
+        match self {
+            RoutingMode::SteepestDescent => drop / distance,
+            RoutingMode::LowestNeighbor => drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steepest_descent_weighs_drops_by_distance() {
+        let (near, far) = ((-1.0, 1.0), (-1.5, 2.0));
+        let steepest = RoutingMode::SteepestDescent;
+        assert!(steepest.score(near.0, near.1) < steepest.score(far.0, far.1));
+        let lowest = RoutingMode::LowestNeighbor;
+        assert!(lowest.score(far.0, far.1) < lowest.score(near.0, near.1));
+    }
+}
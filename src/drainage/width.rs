@@ -0,0 +1,102 @@
+use super::node::DrainageBasinNode;
+
+/// Converts the drainage properties of a node into a river width in map units.
+pub trait RiverWidthModel {
+    fn width(&self, node: &DrainageBasinNode) -> f64;
+}
+
+/// Hydraulic-geometry power law: `coefficient * drainage_area^exponent * scale`.
+///
+/// An exponent of `0.5` reproduces the classic `sqrt(drainage_area) * strength` width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerLawWidth {
+    pub coefficient: f64,
+    pub exponent: f64,
+}
+
+impl PowerLawWidth {
+    pub fn new(coefficient: f64, exponent: f64) -> Self {
+        Self {
+            coefficient,
+            exponent,
+        }
+    }
+
+    pub fn square_root(strength: f64) -> Self {
+        Self::new(strength, 0.5)
+    }
+}
+
+impl RiverWidthModel for PowerLawWidth {
+    fn width(&self, node: &DrainageBasinNode) -> f64 {
+        node.drainage_area.powf(self.exponent) * self.coefficient * node.particle.params().scale
+    }
+}
+
+/// Logarithmic width: `coefficient * ln(1 + drainage_area / reference_area) * scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogarithmicWidth {
+    pub coefficient: f64,
+    pub reference_area: f64,
+}
+
+impl LogarithmicWidth {
+    pub fn new(coefficient: f64, reference_area: f64) -> Self {
+        Self {
+            coefficient,
+            reference_area,
+        }
+    }
+}
+
+impl RiverWidthModel for LogarithmicWidth {
+    fn width(&self, node: &DrainageBasinNode) -> f64 {
+        (node.drainage_area / self.reference_area).ln_1p()
+            * self.coefficient
+            * node.particle.params().scale
+    }
+}
+
+/// Width computed by an arbitrary closure.
+pub struct CustomWidth<F>(pub F)
+where
+    F: Fn(&DrainageBasinNode) -> f64;
+
+impl<F> RiverWidthModel for CustomWidth<F>
+where
+    F: Fn(&DrainageBasinNode) -> f64,
+{
+    fn width(&self, node: &DrainageBasinNode) -> f64 {
+        (self.0)(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::node;
+
+    #[test]
+    fn square_root_width_follows_drainage_area() {
+        let model = PowerLawWidth::square_root(2.0);
+        let node = node(9.0, 0.0);
+        let scale = node.particle.params().scale;
+        assert_eq!(model.width(&node), 6.0 * scale);
+        assert_eq!(PowerLawWidth::new(2.0, 1.0).width(&node), 18.0 * scale);
+    }
+
+    #[test]
+    fn logarithmic_width_starts_at_zero() {
+        let model = LogarithmicWidth::new(3.0, 2.0);
+        let scale = node(0.0, 0.0).particle.params().scale;
+        assert_eq!(model.width(&node(0.0, 0.0)), 0.0);
+        let width = model.width(&node(2.0 * (std::f64::consts::E - 1.0), 0.0));
+        assert!((width - 3.0 * scale).abs() < 1e-12);
+    }
+
+    #[test]
+    fn custom_width_sees_the_node() {
+        let model = CustomWidth(|node: &DrainageBasinNode| node.drainage_area + node.slope);
+        assert_eq!(model.width(&node(2.0, 0.5)), 2.5);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{
+    drainage::map::compare_sites,
+    geometry::{contains, segments, signed_area},
+    voronoi::VoronoiCache,
+};
+
+/// Connected set of flat particles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatRegion {
+    /// Index of the region, by decreasing area.
+    pub id: usize,
+    pub particles: Vec<Particle>,
+    pub area: f64,
+    pub mean_flatness: f64,
+    /// Area-weighted center of the region.
+    pub centroid: (f64, f64),
+}
+
+/// Splits the particles of `flatness_map` into regions of Voronoi neighbors.
+pub(crate) fn label_regions(
+    flatness_map: &ParticleMap<f64>,
+    voronoi: &VoronoiCache,
+) -> Vec<FlatRegion> {
+    let mut particles = flatness_map
+        .iter()
+        .map(|(particle, _)| *particle)
+        .collect::<Vec<_>>();
+    particles.sort_by(compare_sites);
+
+    let mut labeled = HashSet::new();
+    let mut regions = Vec::new();
+    for start in particles {
+        if !labeled.insert(start) {
+            continue;
+        }
+        let mut members = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(current) = queue.pop_front() {
+            members.push(current);
+            for neighbor in voronoi.get_or_compute(&current).neighbors.iter() {
+                if flatness_map.get(neighbor).is_some() && labeled.insert(*neighbor) {
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+
+        let (mut area, mut flatness, mut center) = (0.0, 0.0, (0.0, 0.0));
+        for particle in members.iter() {
+            let cell_area = voronoi.get_or_compute(particle).area;
+            let site = particle.site();
+            area += cell_area;
+            flatness += flatness_map.get(particle).copied().unwrap_or(0.0) * cell_area;
+            center = (center.0 + site.0 * cell_area, center.1 + site.1 * cell_area);
+        }
+        if area <= 0.0 {
+            continue;
+        }
+        regions.push(FlatRegion {
+            id: 0,
+            particles: members,
+            area,
+            mean_flatness: flatness / area,
+            centroid: (center.0 / area, center.1 / area),
+        });
+    }
+
+    regions.sort_by(|a, b| b.area.total_cmp(&a.area));
+    for (id, region) in regions.iter_mut().enumerate() {
+        region.id = id;
+    }
+    regions
+}
+
+/// Outline of a flat region, with the outline counterclockwise and holes clockwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionPolygon {
+    pub outline: Vec<(f64, f64)>,
+    pub holes: Vec<Vec<(f64, f64)>>,
+}
+
+impl RegionPolygon {
+    /// Area of the outline minus its holes.
+    pub fn area(&self) -> f64 {
+        signed_area(&self.outline) + self.holes.iter().map(|hole| signed_area(hole)).sum::<f64>()
+    }
+
+    /// Center of mass of the outline minus its holes. For a region drawn as a single
+    /// polygon this is the region's own [`centroid`](FlatRegion::centroid), up to how far
+    /// the sites lie from the centers of their cells.
+    pub fn centroid(&self) -> (f64, f64) {
+        let moment = std::iter::once(&self.outline)
+            .chain(self.holes.iter())
+            .map(|ring| first_moment(ring))
+            .fold((0.0, 0.0), |sum, moment| {
+                (sum.0 + moment.0, sum.1 + moment.1)
+            });
+        let area = self.area();
+        (moment.0 / area, moment.1 / area)
+    }
+}
+
+impl FlatRegion {
+    /// Merges the Voronoi cells of the region into polygons. A region is normally a single
+    /// polygon, possibly with holes.
+    pub fn polygons(&self, voronoi: &VoronoiCache) -> Vec<RegionPolygon> {
+        let mut vertices = VertexIndex::default();
+        let mut edges = HashSet::new();
+        for particle in self.particles.iter() {
+            let mut polygon = voronoi.get_or_compute(particle).polygon.clone();
+            if signed_area(&polygon) < 0.0 {
+                polygon.reverse();
+            }
+            for i in 0..polygon.len() {
+                let a = vertices.id(polygon[i]);
+                let b = vertices.id(polygon[(i + 1) % polygon.len()]);
+                if a == b {
+                    continue;
+                }
+                // An edge shared with another cell of the region runs the other way there.
+                if !edges.remove(&(b, a)) {
+                    edges.insert((a, b));
+                }
+            }
+        }
+
+        let mut outgoing: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (from, to) in edges.iter() {
+            outgoing.entry(*from).or_default().push(*to);
+        }
+        for targets in outgoing.values_mut() {
+            targets.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        let mut starts = outgoing.keys().copied().collect::<Vec<_>>();
+        starts.sort_unstable();
+
+        let mut rings = Vec::new();
+        for start in starts {
+            while let Some(first) = outgoing.get_mut(&start).and_then(|targets| targets.pop()) {
+                let mut ring = vec![vertices.points[start]];
+                let (mut previous, mut current) = (start, first);
+                while current != start {
+                    ring.push(vertices.points[current]);
+                    let Some(targets) = outgoing.get_mut(&current).filter(|t| !t.is_empty()) else {
+                        break;
+                    };
+                    // Where regions touch at a single vertex, turning left the most keeps
+                    // the ring around one of them instead of crossing into the other.
+                    let points = &vertices.points;
+                    let index = (0..targets.len())
+                        .max_by(|a, b| {
+                            let turn = |target: usize| {
+                                turn_angle(points[previous], points[current], points[target])
+                            };
+                            turn(targets[*a]).total_cmp(&turn(targets[*b]))
+                        })
+                        .unwrap();
+                    (previous, current) = (current, targets.swap_remove(index));
+                }
+                if ring.len() >= 3 {
+                    rings.push(ring);
+                }
+            }
+        }
+
+        let (outlines, holes): (Vec<_>, Vec<_>) =
+            rings.into_iter().partition(|ring| signed_area(ring) > 0.0);
+        let mut polygons = outlines
+            .into_iter()
+            .map(|outline| RegionPolygon {
+                outline,
+                holes: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        for hole in holes {
+            let container = polygons
+                .iter_mut()
+                .filter(|polygon| contains(&polygon.outline, hole[0]))
+                .min_by(|a, b| signed_area(&a.outline).total_cmp(&signed_area(&b.outline)));
+            if let Some(polygon) = container {
+                polygon.holes.push(hole);
+            }
+        }
+        polygons
+    }
+}
+
+/// Vertices of neighboring cells are computed separately and may differ slightly, so they
+/// are merged when closer than the tolerance.
+#[derive(Default)]
+struct VertexIndex {
+    points: Vec<(f64, f64)>,
+    grid: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl VertexIndex {
+    const TOLERANCE: f64 = 1e-9;
+
+    fn id(&mut self, point: (f64, f64)) -> usize {
+        let cell = (
+            (point.0 / Self::TOLERANCE).floor() as i64,
+            (point.1 / Self::TOLERANCE).floor() as i64,
+        );
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let Some(ids) = self.grid.get(&(cell.0 + dx, cell.1 + dy)) else {
+                    continue;
+                };
+                for id in ids {
+                    let other = self.points[*id];
+                    if (other.0 - point.0).hypot(other.1 - point.1) <= Self::TOLERANCE {
+                        return *id;
+                    }
+                }
+            }
+        }
+        let id = self.points.len();
+        self.points.push(point);
+        self.grid.entry(cell).or_default().push(id);
+        id
+    }
+}
+
+/// Signed angle turned at `b` when going from `a` through `b` to `c`, positive to the left.
+fn turn_angle(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    let (u, v) = ((b.0 - a.0, b.1 - a.1), (c.0 - b.0, c.1 - b.1));
+    (u.0 * v.1 - u.1 * v.0).atan2(u.0 * v.0 + u.1 * v.1)
+}
+
+/// Integral of `x` and of `y` over the ring, signed like [`signed_area`].
+fn first_moment(ring: &[(f64, f64)]) -> (f64, f64) {
+    let (x, y) = segments(ring)
+        .map(|(a, b)| {
+            let cross = a.0 * b.1 - b.0 * a.1;
+            ((a.0 + b.0) * cross, (a.1 + b.1) * cross)
+        })
+        .fold((0.0, 0.0), |sum, term| (sum.0 + term.0, sum.1 + term.1));
+    (x / 6.0, y / 6.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::particles;
+
+    /// Flatness map over the particles for which `flatness` returns a value, with sites in
+    /// particle spacings.
+    fn flatness_map(flatness: impl Fn(f64, f64) -> Option<f64>) -> ParticleMap<f64> {
+        let map = particles(8.0, 8.0)
+            .into_iter()
+            .map(|particle| (particle, 0.0))
+            .collect::<ParticleMap<f64>>();
+        let scale = map.params().scale;
+        map.iter()
+            .filter_map(|(particle, _)| {
+                let site = particle.site();
+                Some((*particle, flatness(site.0 / scale, site.1 / scale)?))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn regions_are_labeled_by_decreasing_area() {
+        let flatness_map = flatness_map(|x, y| match (x, y) {
+            _ if x < 2.0 && y < 2.0 => Some(if x < 1.0 { 0.2 } else { 0.6 }),
+            _ if x > 4.0 && y > 3.0 && y < 5.0 => Some(0.5),
+            _ => None,
+        });
+        let voronoi = VoronoiCache::new(&flatness_map);
+        let scale = flatness_map.params().scale;
+
+        let regions = label_regions(&flatness_map, &voronoi);
+        assert_eq!(regions.len(), 2);
+        assert_eq!(
+            regions.iter().map(|region| region.id).collect::<Vec<_>>(),
+            [0, 1]
+        );
+
+        let (large, small) = (&regions[0], &regions[1]);
+        assert_eq!(large.particles.len(), 8);
+        assert!((large.area - 8.0 * scale * scale).abs() < 1e-9);
+        assert!((large.mean_flatness - 0.5).abs() < 1e-9);
+        assert!((large.centroid.0 - 6.0 * scale).abs() < 1e-9);
+        assert!((large.centroid.1 - 4.0 * scale).abs() < 1e-9);
+
+        assert_eq!(small.particles.len(), 4);
+        assert!((small.mean_flatness - 0.4).abs() < 1e-9);
+        assert!((small.centroid.0 - scale).abs() < 1e-9);
+        assert!((small.centroid.1 - scale).abs() < 1e-9);
+    }
+
+    #[test]
+    fn merged_cells_outline_the_region_around_its_holes() {
+        // A 4 x 4 block with one particle missing inside it.
+        let flatness_map = flatness_map(|x, y| {
+            let inside = (1.0..5.0).contains(&x) && (1.0..5.0).contains(&y);
+            (inside && (x, y) != (2.5, 3.5)).then_some(1.0)
+        });
+        let voronoi = VoronoiCache::new(&flatness_map);
+        let scale = flatness_map.params().scale;
+        let regions = label_regions(&flatness_map, &voronoi);
+        assert_eq!(regions.len(), 1);
+
+        let polygons = regions[0].polygons(&voronoi);
+        assert_eq!(polygons.len(), 1);
+        let polygon = &polygons[0];
+        assert!((signed_area(&polygon.outline) - 16.0 * scale * scale).abs() < 1e-9);
+        assert_eq!(polygon.holes.len(), 1);
+        assert!((signed_area(&polygon.holes[0]) + scale * scale).abs() < 1e-9);
+        assert!((polygon.area() - regions[0].area).abs() < 1e-9);
+        // The block centered on (3, 3) without the cell centered on the hole.
+        let centroid = polygon.centroid();
+        assert!((centroid.0 - (16.0 * 3.0 - 2.5) / 15.0 * scale).abs() < 1e-9);
+        assert!((centroid.1 - (16.0 * 3.0 - 3.5) / 15.0 * scale).abs() < 1e-9);
+        assert!((centroid.0 - regions[0].centroid.0).abs() < 1e-9);
+        assert!((centroid.1 - regions[0].centroid.1).abs() < 1e-9);
+
+        for particle in regions[0].particles.iter() {
+            assert!(contains(&polygon.outline, particle.site()));
+            assert!(!contains(&polygon.holes[0], particle.site()));
+        }
+        let hole_site = (2.5 * scale, 3.5 * scale);
+        assert!(contains(&polygon.outline, hole_site));
+        assert!(contains(&polygon.holes[0], hole_site));
+    }
+
+    #[test]
+    fn cells_touching_at_a_corner_stay_separate_polygons() {
+        let flatness_map = flatness_map(|x, y| {
+            let block =
+                |x0: f64, y0: f64| (x0..x0 + 2.0).contains(&x) && (y0..y0 + 2.0).contains(&y);
+            (block(1.0, 1.0) || block(3.0, 3.0)).then_some(1.0)
+        });
+        let voronoi = VoronoiCache::new(&flatness_map);
+        let scale = flatness_map.params().scale;
+        let region = FlatRegion {
+            id: 0,
+            particles: flatness_map.iter().map(|(particle, _)| *particle).collect(),
+            area: 8.0 * scale * scale,
+            mean_flatness: 1.0,
+            centroid: (3.0 * scale, 3.0 * scale),
+        };
+
+        let polygons = region.polygons(&voronoi);
+        assert_eq!(polygons.len(), 2);
+        let mut centroids = Vec::new();
+        for polygon in polygons {
+            assert!(polygon.holes.is_empty());
+            assert!((polygon.area() - 4.0 * scale * scale).abs() < 1e-9);
+            centroids.push(polygon.centroid());
+        }
+        centroids.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (centroid, center) in centroids.into_iter().zip([2.0, 4.0]) {
+            assert!((centroid.0 - center * scale).abs() < 1e-9);
+            assert!((centroid.1 - center * scale).abs() < 1e-9);
+        }
+    }
+}
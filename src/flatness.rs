@@ -1,7 +1,24 @@
-use worley_particle::map::{
-    grad::{GradDifferenceType, GradStrategy},
-    lerp::InterpolationMethod,
-    IDWStrategy, ParticleMap,
+use std::collections::HashSet;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use worley_particle::{
+    map::{
+        grad::{GradDifferenceType, GradStrategy},
+        lerp::InterpolationMethod,
+        rw::ParticleMapAttributeRW,
+        IDWStrategy, ParticleMap,
+    },
+    Particle,
+};
+
+use crate::{
+    boundary::{Boundary, BoundaryPolicy},
+    flat_region::{label_regions, FlatRegion},
+    rw::write_sorted_to_file,
+    sea::SeaMask,
+    slope::estimate_slope,
+    voronoi::VoronoiCache,
 };
 
 // fn gradient_to_flatness(gradient: f64) -> Option<f64> {
@@ -12,8 +29,73 @@ use worley_particle::map::{
 //     Some(flatness.sqrt())
 // }
 
+/// The terrain measure passed to the flatness function.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FlatnessMetric {
+    /// `value` of the interpolated gradient, as returned by `ParticleMap::get_gradient`.
+    #[default]
+    GradientValue,
+    /// Magnitude of the slope vector fitted through the particle and its neighbors: the
+    /// rise per unit distance along the steepest way down, whichever way that faces.
+    SlopeMagnitude,
+    /// Difference between the highest and lowest elevation within `radius`.
+    LocalRelief { radius: f64 },
+}
+
+/// Grayscale morphology applied to the flatness map, treating missing particles as zero
+/// flatness. Opening removes flat patches narrower than the radius, closing fills gaps
+/// narrower than it; openings are applied first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Morphology {
+    /// Particles within this distance of each other are neighbors.
+    pub radius: f64,
+    pub opening_iterations: usize,
+    pub closing_iterations: usize,
+}
+
+/// Why a particle has, or does not have, a flatness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlatnessClass {
+    /// Below the sea level.
+    Sea,
+    /// Rejected by the flatness function, or no metric could be computed.
+    Steep,
+    /// Flat enough, but removed by the neighbor, morphology or region filters.
+    Filtered,
+    Flat(f64),
+}
+
+impl ParticleMapAttributeRW for FlatnessClass {
+    fn from_strs(s: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        match s[0] {
+            "sea" => Ok(FlatnessClass::Sea),
+            "steep" => Ok(FlatnessClass::Steep),
+            "filtered" => Ok(FlatnessClass::Filtered),
+            "flat" => Ok(FlatnessClass::Flat(s[1].parse::<f64>()?)),
+            other => Err(format!("Unknown flatness class: {}", other).into()),
+        }
+    }
+
+    fn to_strings(&self) -> Vec<String> {
+        let (class, value) = match self {
+            FlatnessClass::Sea => ("sea", 0.0),
+            FlatnessClass::Steep => ("steep", 0.0),
+            FlatnessClass::Filtered => ("filtered", 0.0),
+            FlatnessClass::Flat(flatness) => ("flat", *flatness),
+        };
+        vec![class.to_string(), value.to_string()]
+    }
+
+    fn len_strs() -> usize {
+        2
+    }
+}
+
 pub struct FlatnessMap {
     pub particle_map: ParticleMap<f64>,
+    classification: ParticleMap<FlatnessClass>,
+    sea_mask: SeaMask,
+    voronoi: VoronoiCache,
 }
 
 impl FlatnessMap {
@@ -21,46 +103,217 @@ impl FlatnessMap {
         elevation_map: &ParticleMap<f64>,
         minimum_neighbor_num: usize,
         sea_level: f64,
-        gradient_to_flatness: impl Fn(f64) -> Option<f64>,
+        boundary_policy: BoundaryPolicy,
+        gradient_to_flatness: impl Fn(f64) -> Option<f64> + Sync,
     ) -> Self {
-        let particle_map = build_flatness_map(
-            elevation_map,
-            minimum_neighbor_num,
-            sea_level,
-            gradient_to_flatness,
-        );
-        Self { particle_map }
+        FlatnessMapBuilder::new(elevation_map)
+            .minimum_neighbor_num(minimum_neighbor_num)
+            .sea_level(sea_level)
+            .boundary_policy(boundary_policy)
+            .build(gradient_to_flatness)
+    }
+
+    pub fn new_with_voronoi(
+        elevation_map: &ParticleMap<f64>,
+        voronoi: &VoronoiCache,
+        minimum_neighbor_num: usize,
+        sea_level: f64,
+        boundary_policy: BoundaryPolicy,
+        gradient_to_flatness: impl Fn(f64) -> Option<f64> + Sync,
+    ) -> Self {
+        FlatnessMapBuilder::new(elevation_map)
+            .voronoi(voronoi)
+            .minimum_neighbor_num(minimum_neighbor_num)
+            .sea_level(sea_level)
+            .boundary_policy(boundary_policy)
+            .build(gradient_to_flatness)
     }
 
     pub fn save_to_file(&self, file_path: &str) {
-        self.particle_map
-            .write_to_file(file_path)
-            .expect("Error writing drainage map");
+        write_sorted_to_file(&self.particle_map, file_path).expect("Error writing drainage map");
     }
 
+    pub fn save_classification_to_file(&self, file_path: &str) {
+        write_sorted_to_file(&self.classification, file_path)
+            .expect("Error writing flatness classification");
+    }
+
+    /// Only the flat particles are stored in the file, so every particle of the loaded map
+    /// is classified as flat and the sea mask is empty. See [`Self::load_from_files`] to
+    /// keep the classification.
     pub fn load_from_file(file_path: &str) -> Option<Self> {
         let particle_map = ParticleMap::<f64>::read_from_file(file_path).ok()?;
-        Some(Self { particle_map })
+        let classification = particle_map
+            .iter()
+            .map(|(particle, flatness)| (*particle, FlatnessClass::Flat(*flatness)))
+            .collect::<ParticleMap<FlatnessClass>>();
+        Some(Self {
+            sea_mask: SeaMask::new(&particle_map, f64::NEG_INFINITY),
+            particle_map,
+            classification,
+            voronoi: VoronoiCache::default(),
+        })
+    }
+
+    /// Loads a map saved with both [`Self::save_to_file`] and
+    /// [`Self::save_classification_to_file`], restoring the classification and the sea mask.
+    /// The sea level is not stored, so the loaded sea mask reports it as NaN.
+    pub fn load_from_files(file_path: &str, classification_path: &str) -> Option<Self> {
+        let particle_map = ParticleMap::<f64>::read_from_file(file_path).ok()?;
+        let classification =
+            ParticleMap::<FlatnessClass>::read_from_file(classification_path).ok()?;
+        let sea = classification
+            .iter()
+            .filter(|(_, class)| **class == FlatnessClass::Sea)
+            .map(|(particle, _)| *particle);
+        Some(Self {
+            sea_mask: SeaMask::from_particles(sea, f64::NAN),
+            particle_map,
+            classification,
+            voronoi: VoronoiCache::default(),
+        })
     }
 
     pub fn map(&self) -> &ParticleMap<f64> {
         &self.particle_map
     }
+
+    pub fn classification(&self) -> &ParticleMap<FlatnessClass> {
+        &self.classification
+    }
+
+    pub fn sea_mask(&self) -> &SeaMask {
+        &self.sea_mask
+    }
+
+    pub fn voronoi(&self) -> &VoronoiCache {
+        &self.voronoi
+    }
+
+    /// Connected flat regions of at least `minimum_area`, largest first.
+    pub fn regions(&self, minimum_area: f64) -> Vec<FlatRegion> {
+        label_regions(&self.particle_map, &self.voronoi)
+            .into_iter()
+            .filter(|region| region.area >= minimum_area)
+            .collect()
+    }
 }
 
-fn build_flatness_map(
-    elevation_map: &ParticleMap<f64>,
+/// Configures and builds a [`FlatnessMap`]. Options left unset keep every particle and
+/// use the gradient value.
+pub struct FlatnessMapBuilder<'a> {
+    elevation_map: &'a ParticleMap<f64>,
+    voronoi: Option<VoronoiCache>,
     minimum_neighbor_num: usize,
     sea_level: f64,
-    gradient_to_flatness: impl Fn(f64) -> Option<f64>,
-) -> ParticleMap<f64> {
-    let mut flatness_map = elevation_map
-        .iter()
-        .filter_map(|(particle, elevation)| {
-            if *elevation < sea_level {
-                return None;
-            }
-            let (x, y) = particle.site();
+    boundary_policy: BoundaryPolicy,
+    metric: FlatnessMetric,
+    morphology: Option<Morphology>,
+    minimum_region_area: f64,
+}
+
+impl<'a> FlatnessMapBuilder<'a> {
+    pub fn new(elevation_map: &'a ParticleMap<f64>) -> Self {
+        Self {
+            elevation_map,
+            voronoi: None,
+            minimum_neighbor_num: 0,
+            sea_level: f64::NEG_INFINITY,
+            boundary_policy: BoundaryPolicy::default(),
+            metric: FlatnessMetric::default(),
+            morphology: None,
+            minimum_region_area: 0.0,
+        }
+    }
+
+    pub fn voronoi(mut self, voronoi: &VoronoiCache) -> Self {
+        self.voronoi = Some(voronoi.clone());
+        self
+    }
+
+    /// Drops flat particles with fewer flat neighbors than this.
+    pub fn minimum_neighbor_num(mut self, minimum_neighbor_num: usize) -> Self {
+        self.minimum_neighbor_num = minimum_neighbor_num;
+        self
+    }
+
+    pub fn sea_level(mut self, sea_level: f64) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    pub fn boundary_policy(mut self, boundary_policy: BoundaryPolicy) -> Self {
+        self.boundary_policy = boundary_policy;
+        self
+    }
+
+    /// Measure passed to the flatness function given to [`build`](Self::build). Each
+    /// metric has its own scale, so the thresholds of that function have to be chosen for
+    /// the metric: a slope magnitude of 1 rises one unit of elevation per unit of
+    /// distance, while a gradient value depends on how `get_gradient` interpolates.
+    /// Flatness never depends on the direction the terrain faces; use
+    /// [`AspectMap`](crate::aspect::AspectMap) for that.
+    pub fn metric(mut self, metric: FlatnessMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn morphology(mut self, morphology: Morphology) -> Self {
+        self.morphology = Some(morphology);
+        self
+    }
+
+    /// Drops connected flat regions smaller than this area.
+    pub fn minimum_region_area(mut self, minimum_region_area: f64) -> Self {
+        self.minimum_region_area = minimum_region_area;
+        self
+    }
+
+    /// `metric_to_flatness` turns the chosen metric into a flatness, or `None` for
+    /// particles that are not flat at all.
+    pub fn build(self, metric_to_flatness: impl Fn(f64) -> Option<f64> + Sync) -> FlatnessMap {
+        let voronoi = self
+            .voronoi
+            .clone()
+            .unwrap_or_else(|| VoronoiCache::new(self.elevation_map));
+        let sea_mask = SeaMask::new(self.elevation_map, self.sea_level);
+        let (particle_map, steep) =
+            build_flatness_map(&self, &voronoi, &sea_mask, metric_to_flatness);
+        let classification = self
+            .elevation_map
+            .iter()
+            .map(|(particle, _)| {
+                let class = if sea_mask.is_sea(particle) {
+                    FlatnessClass::Sea
+                } else if let Some(flatness) = particle_map.get(particle) {
+                    FlatnessClass::Flat(*flatness)
+                } else if steep.contains(particle) {
+                    FlatnessClass::Steep
+                } else {
+                    FlatnessClass::Filtered
+                };
+                (*particle, class)
+            })
+            .collect::<ParticleMap<FlatnessClass>>();
+        FlatnessMap {
+            particle_map,
+            classification,
+            sea_mask,
+            voronoi,
+        }
+    }
+}
+
+fn metric_value(
+    elevation_map: &ParticleMap<f64>,
+    voronoi: &VoronoiCache,
+    boundary: &Boundary<f64>,
+    metric: FlatnessMetric,
+    particle: &Particle,
+) -> Option<f64> {
+    let (x, y) = particle.site();
+    match metric {
+        FlatnessMetric::GradientValue => {
             let gradient = elevation_map.get_gradient(
                 x,
                 y,
@@ -71,65 +324,412 @@ fn build_flatness_map(
                 },
                 &InterpolationMethod::IDW(IDWStrategy::default_from_params(elevation_map.params())),
             )?;
-            let habitability = gradient_to_flatness(gradient.value)?;
-            Some((*particle, habitability))
+            Some(gradient.value)
+        }
+        FlatnessMetric::SlopeMagnitude => {
+            estimate_slope(elevation_map, voronoi, boundary, particle)
+                .map(|slope| slope.magnitude())
+        }
+        FlatnessMetric::LocalRelief { radius } => {
+            let (lowest, highest) =
+                Particle::from_inside_radius(x, y, *elevation_map.params(), radius)
+                    .iter()
+                    .filter_map(|particle| elevation_map.get(particle))
+                    .fold((f64::MAX, f64::MIN), |(lowest, highest), elevation| {
+                        (lowest.min(*elevation), highest.max(*elevation))
+                    });
+            (lowest <= highest).then_some(highest - lowest)
+        }
+    }
+}
+
+/// Returns the flatness of the flat particles, and the land particles that are too steep.
+fn build_flatness_map(
+    options: &FlatnessMapBuilder,
+    voronoi: &VoronoiCache,
+    sea_mask: &SeaMask,
+    metric_to_flatness: impl Fn(f64) -> Option<f64> + Sync,
+) -> (ParticleMap<f64>, HashSet<Particle>) {
+    let FlatnessMapBuilder {
+        elevation_map,
+        minimum_neighbor_num,
+        boundary_policy,
+        metric,
+        ..
+    } = *options;
+    let boundary = Boundary::new(elevation_map, boundary_policy);
+    let elevations = elevation_map.iter().collect::<Vec<_>>();
+
+    let elevations_iter = par_iter!(elevations);
+
+    let land = elevations_iter
+        .filter(|(particle, _)| !sea_mask.is_sea(particle))
+        .map(|&(particle, _)| {
+            let flatness = metric_value(elevation_map, voronoi, &boundary, metric, particle)
+                .and_then(&metric_to_flatness);
+            (*particle, flatness)
         })
+        .collect::<Vec<_>>();
+    let steep = land
+        .iter()
+        .filter(|(_, flatness)| flatness.is_none())
+        .map(|(particle, _)| *particle)
+        .collect::<HashSet<_>>();
+    let mut flatness_map = land
+        .iter()
+        .filter_map(|(particle, flatness)| Some((*particle, (*flatness)?)))
         .collect::<ParticleMap<f64>>();
 
     if minimum_neighbor_num > 0 {
-        flatness_map = flatness_map
-            .iter()
+        let flatness = flatness_map.iter().collect::<Vec<_>>();
+
+        let flatness_iter = par_iter!(flatness);
+
+        let filtered = flatness_iter
             .filter(|(particle, _)| {
-                let surrounding_particles = particle.calculate_voronoi().neighbors;
-                let count = surrounding_particles
+                let surrounding_particles =
+                    boundary.resolve(particle, &voronoi.get_or_compute(particle).neighbors);
+                let mut count = surrounding_particles
+                    .inside
                     .iter()
-                    .filter(|neighbor| flatness_map.get(neighbor).is_some())
+                    .filter(|(neighbor, _)| flatness_map.get(neighbor).is_some())
                     .count();
+                if boundary_policy == BoundaryPolicy::Open {
+                    count += surrounding_particles.outside;
+                }
 
                 count >= minimum_neighbor_num
             })
-            .map(|(particle, flatness)| (*particle, *flatness))
+            .map(|(particle, flatness)| (**particle, **flatness))
+            .collect::<Vec<_>>();
+        flatness_map = filtered.into_iter().collect::<ParticleMap<f64>>();
+    }
+
+    if let Some(morphology) = options.morphology {
+        let land = land
+            .iter()
+            .map(|(particle, _)| *particle)
+            .collect::<Vec<_>>();
+        let operations = std::iter::repeat_n(Operation::Erode, morphology.opening_iterations)
+            .chain(std::iter::repeat_n(
+                Operation::Dilate,
+                morphology.opening_iterations,
+            ))
+            .chain(std::iter::repeat_n(
+                Operation::Dilate,
+                morphology.closing_iterations,
+            ))
+            .chain(std::iter::repeat_n(
+                Operation::Erode,
+                morphology.closing_iterations,
+            ));
+        for operation in operations {
+            flatness_map = apply_operation(
+                &flatness_map,
+                elevation_map,
+                &land,
+                sea_mask,
+                morphology.radius,
+                operation,
+            );
+        }
+    }
+
+    if options.minimum_region_area > 0.0 {
+        flatness_map = label_regions(&flatness_map, voronoi)
+            .into_iter()
+            .filter(|region| region.area >= options.minimum_region_area)
+            .flat_map(|region| region.particles)
+            .filter_map(|particle| Some((particle, *flatness_map.get(&particle)?)))
             .collect::<ParticleMap<f64>>();
     }
 
-    flatness_map
+    (flatness_map, steep)
 }
 
-#[cfg(feature = "visualize")]
-mod visualization {
-    use gtk4::{cairo::Context, prelude::WidgetExt, DrawingArea};
-    use vislayers::{geometry::FocusRange, window::Layer};
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Erode,
+    Dilate,
+}
 
-    use super::FlatnessMap;
+fn apply_operation(
+    flatness_map: &ParticleMap<f64>,
+    elevation_map: &ParticleMap<f64>,
+    land: &[Particle],
+    sea_mask: &SeaMask,
+    radius: f64,
+    operation: Operation,
+) -> ParticleMap<f64> {
+    let land_iter = par_iter!(land);
 
-    impl Layer for FlatnessMap {
-        fn draw(&self, drawing_area: &DrawingArea, ctx: &Context, focus_range: &FocusRange) {
-            let area_width = drawing_area.width();
-            let area_height = drawing_area.height();
+    land_iter
+        .filter_map(|particle| {
+            let (x, y) = particle.site();
+            let values = std::iter::once(*particle)
+                .chain(Particle::from_inside_radius(
+                    x,
+                    y,
+                    *elevation_map.params(),
+                    radius,
+                ))
+                .filter(|neighbor| {
+                    elevation_map.get(neighbor).is_some() && !sea_mask.is_sea(neighbor)
+                })
+                .map(|neighbor| flatness_map.get(&neighbor).copied().unwrap_or(0.0));
+            let value = match operation {
+                Operation::Erode => values.fold(f64::MAX, f64::min),
+                Operation::Dilate => values.fold(0.0, f64::max),
+            };
+            (value > 0.0).then_some((*particle, value))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .collect::<ParticleMap<f64>>()
+}
 
-            let rect = focus_range.to_rect(area_width as f64, area_height as f64);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{particle_at, terrain};
 
-            for (particle, flatness) in self.particle_map.iter() {
-                let color = [1.0, 0.5, 0.0];
+    fn level(slope: f64) -> Option<f64> {
+        (slope < 1e-9).then_some(1.0)
+    }
+
+    /// Flat plain for x < 3, rugged slopes beyond it with a terrace around (7.5, 2.5)
+    /// whose center particle is flat.
+    fn plain_and_terrace() -> ParticleMap<f64> {
+        terrain(10.0, 6.0, |x, y| {
+            if x < 4.0 {
+                0.0
+            } else if (6.0..9.0).contains(&x) && (1.0..4.0).contains(&y) {
+                5.0
+            } else {
+                (x - 3.0) * 0.5 + ((x + y) as i64 % 2) as f64 * 0.3
+            }
+        })
+    }
 
-                ctx.set_source_rgba(color[0], color[1], color[2], *flatness);
+    fn flat_sites(map: &FlatnessMap) -> Vec<(f64, f64)> {
+        let scale = map.map().params().scale;
+        let mut sites = map
+            .map()
+            .iter()
+            .map(|(particle, _)| {
+                let site = particle.site();
+                (site.0 / scale, site.1 / scale)
+            })
+            .collect::<Vec<_>>();
+        sites.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        sites
+    }
 
-                let polygon = particle.calculate_voronoi().polygon;
+    fn plain_sites() -> Vec<(f64, f64)> {
+        [0.5, 1.5, 2.5]
+            .into_iter()
+            .flat_map(|x| [0.5, 1.5, 2.5, 3.5, 4.5, 5.5].map(|y| (x, y)))
+            .collect()
+    }
 
-                ctx.new_path();
-                for (i, point) in polygon.iter().enumerate() {
-                    let x = rect.map_coord_x(point.0, 0.0, area_width as f64);
-                    let y = rect.map_coord_y(point.1, 0.0, area_height as f64);
+    #[test]
+    fn particles_are_classified_by_why_they_are_not_flat() {
+        let elevation_map = plain_and_terrace();
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::SlopeMagnitude)
+            .sea_level(0.0)
+            .minimum_region_area(2.0 * elevation_map.params().scale.powi(2))
+            .build(level);
+        let class = |x: f64, y: f64| {
+            *flatness_map
+                .classification()
+                .get(&particle_at(&elevation_map, x, y))
+                .unwrap()
+        };
+        assert_eq!(flatness_map.classification().iter().count(), 60);
+        // The plain is at the sea level, not below it.
+        assert_eq!(class(0.5, 0.5), FlatnessClass::Flat(1.0));
+        assert_eq!(class(5.5, 0.5), FlatnessClass::Steep);
+        assert_eq!(class(7.5, 2.5), FlatnessClass::Filtered);
+        assert!(flatness_map.sea_mask().is_empty());
 
-                    if i == 0 {
-                        ctx.move_to(x, y);
-                    } else {
-                        ctx.line_to(x, y);
-                    }
+        let drowned = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::SlopeMagnitude)
+            .sea_level(0.1)
+            .build(level);
+        for (particle, elevation) in elevation_map.iter() {
+            let class = drowned.classification().get(particle).unwrap();
+            assert_eq!(*class == FlatnessClass::Sea, *elevation < 0.1);
+            assert_eq!(drowned.sea_mask().is_sea(particle), *elevation < 0.1);
+            assert_eq!(
+                drowned.map().get(particle).is_some(),
+                matches!(class, FlatnessClass::Flat(_))
+            );
+        }
+    }
+
+    #[test]
+    fn classification_survives_saving_and_loading() {
+        let elevation_map = plain_and_terrace();
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::SlopeMagnitude)
+            .sea_level(0.1)
+            .minimum_neighbor_num(4)
+            .build(level);
+        assert!(!flatness_map.sea_mask().is_empty());
+
+        let directory = std::env::temp_dir().join(format!("flatness-save-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let [path, classification_path] = ["flatness", "classification"]
+            .map(|name| directory.join(name).to_string_lossy().into_owned());
+        flatness_map.save_to_file(&path);
+        flatness_map.save_classification_to_file(&classification_path);
+        let loaded = FlatnessMap::load_from_files(&path, &classification_path);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let loaded = loaded.unwrap();
+
+        for (particle, class) in flatness_map.classification().iter() {
+            assert_eq!(loaded.classification().get(particle), Some(class));
+            assert_eq!(
+                loaded.sea_mask().is_sea(particle),
+                flatness_map.sea_mask().is_sea(particle)
+            );
+            assert_eq!(loaded.map().get(particle), flatness_map.map().get(particle));
+        }
+        assert_eq!(loaded.sea_mask().len(), flatness_map.sea_mask().len());
+        assert!(loaded.sea_mask().sea_level().is_nan());
+    }
+
+    #[test]
+    fn opening_removes_patches_narrower_than_the_radius() {
+        let elevation_map = plain_and_terrace();
+        let scale = elevation_map.params().scale;
+        let builder =
+            || FlatnessMapBuilder::new(&elevation_map).metric(FlatnessMetric::SlopeMagnitude);
+
+        let raw = builder().build(level);
+        let mut expected = plain_sites();
+        expected.push((7.5, 2.5));
+        assert_eq!(flat_sites(&raw), expected);
+
+        let opened = builder()
+            .morphology(Morphology {
+                radius: 1.01 * scale,
+                opening_iterations: 1,
+                closing_iterations: 0,
+            })
+            .build(level);
+        assert_eq!(flat_sites(&opened), plain_sites());
+        let terrace = particle_at(&elevation_map, 7.5, 2.5);
+        assert_eq!(
+            opened.classification().get(&terrace),
+            Some(&FlatnessClass::Filtered)
+        );
+    }
+
+    #[test]
+    fn closing_fills_gaps_narrower_than_the_radius() {
+        // A spike makes its neighbors steep; symmetric around it, the spike itself fits a
+        // level plane.
+        let elevation_map = terrain(
+            6.0,
+            6.0,
+            |x, y| {
+                if (x, y) == (2.5, 2.5) {
+                    1.0
+                } else {
+                    0.0
                 }
+            },
+        );
+        let scale = elevation_map.params().scale;
+        let builder =
+            || FlatnessMapBuilder::new(&elevation_map).metric(FlatnessMetric::SlopeMagnitude);
+        let flank = particle_at(&elevation_map, 1.5, 2.5);
 
-                ctx.fill().expect("Failed to draw place");
-            }
+        let raw = builder().build(level);
+        assert_eq!(raw.map().iter().count(), elevation_map.iter().count() - 4);
+        assert_eq!(
+            raw.classification().get(&flank),
+            Some(&FlatnessClass::Steep)
+        );
+
+        let closed = builder()
+            .morphology(Morphology {
+                radius: 1.01 * scale,
+                opening_iterations: 0,
+                closing_iterations: 1,
+            })
+            .build(level);
+        assert_eq!(closed.map().iter().count(), elevation_map.iter().count());
+        assert_eq!(
+            closed.classification().get(&flank),
+            Some(&FlatnessClass::Flat(1.0))
+        );
+    }
+
+    #[test]
+    fn small_regions_are_dropped() {
+        let elevation_map = plain_and_terrace();
+        let scale = elevation_map.params().scale;
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::SlopeMagnitude)
+            .minimum_region_area(2.0 * scale * scale)
+            .build(level);
+        assert_eq!(flat_sites(&flatness_map), plain_sites());
+
+        let regions = flatness_map.regions(0.0);
+        assert_eq!(regions.len(), 1);
+        let plain = &regions[0];
+        assert_eq!(plain.id, 0);
+        assert_eq!(plain.particles.len(), 18);
+        assert!((plain.area - 18.0 * scale * scale).abs() < 1e-9);
+        assert_eq!(plain.mean_flatness, 1.0);
+        assert!((plain.centroid.0 - 1.5 * scale).abs() < 1e-9);
+        assert!((plain.centroid.1 - 3.0 * scale).abs() < 1e-9);
+        assert!(flatness_map.regions(19.0 * scale * scale).is_empty());
+    }
+
+    #[test]
+    fn metrics_measure_planes() {
+        let scale = terrain(1.0, 1.0, |_, _| 0.0).params().scale;
+        let elevation_map = terrain(6.0, 6.0, |x, y| (0.3 * x + 0.4 * y) * scale);
+
+        let slope = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::SlopeMagnitude)
+            .build(Some);
+        for (_, value) in slope.map().iter() {
+            assert!((value - 0.5).abs() < 1e-9);
+        }
+
+        // Within a radius of one spacing, the relief is at most the slope across the
+        // diameter.
+        let relief = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::LocalRelief { radius: scale })
+            .build(Some);
+        assert_eq!(relief.map().iter().count(), elevation_map.iter().count());
+        for (_, value) in relief.map().iter() {
+            assert!(*value > 0.0 && *value <= scale + 1e-9);
+        }
+    }
+}
+
+#[cfg(feature = "visualize")]
+mod visualization {
+    use gtk4::{cairo::Context, DrawingArea};
+    use vislayers::{geometry::FocusRange, window::Layer};
+
+    use super::FlatnessMap;
+
+    impl Layer for FlatnessMap {
+        fn draw(&self, drawing_area: &DrawingArea, ctx: &Context, focus_range: &FocusRange) {
+            self.voronoi.fill_cells(
+                drawing_area,
+                ctx,
+                focus_range,
+                self.particle_map.iter(),
+                |flatness| [1.0, 0.5, 0.0, *flatness],
+            );
         }
     }
 }
@@ -0,0 +1,35 @@
+/// Segments of the ring, including the one joining its last point to its first.
+pub(crate) fn segments(ring: &[(f64, f64)]) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+    (0..ring.len()).map(|i| (ring[i], ring[(i + 1) % ring.len()]))
+}
+
+/// Area of the ring, positive if it runs counterclockwise.
+pub(crate) fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    segments(ring)
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum::<f64>()
+        / 2.0
+}
+
+/// Whether the point lies inside the ring, by the even-odd rule.
+pub(crate) fn contains(ring: &[(f64, f64)], point: (f64, f64)) -> bool {
+    let mut inside = false;
+    for (a, b) in segments(ring) {
+        if (a.1 > point.1) != (b.1 > point.1)
+            && point.0 < a.0 + (point.1 - a.1) / (b.1 - a.1) * (b.0 - a.0)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+pub(crate) fn closest_on_segment(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared == 0.0 {
+        return a;
+    }
+    let t = (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0);
+    (a.0 + t * dx, a.1 + t * dy)
+}
@@ -1,2 +1,30 @@
+/// Iterates over a collection with rayon when the `parallel` feature is enabled, and
+/// sequentially otherwise. Callers import `rayon::prelude::*` under the same feature.
+macro_rules! par_iter {
+    ($collection:expr) => {{
+        #[cfg(feature = "parallel")]
+        let iter = $collection.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let iter = $collection.iter();
+        iter
+    }};
+}
+
+pub mod aspect;
+pub mod boundary;
+pub mod coastline;
+pub mod crossing;
 pub mod drainage;
+pub mod flat_region;
 pub mod flatness;
+mod geometry;
+pub mod road;
+mod rw;
+pub mod sea;
+pub mod settlement;
+pub mod slope;
+pub mod suitability;
+pub mod voronoi;
+
+#[cfg(test)]
+mod test_util;
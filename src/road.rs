@@ -0,0 +1,352 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{
+    drainage::map::DrainageMap,
+    settlement::Settlement,
+    voronoi::{LowestFirst, VoronoiCache},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoadParams {
+    /// Extra cost per unit of absolute slope, relative to the travelled distance.
+    pub slope_cost: f64,
+    /// Steps steeper than this (absolute slope) are impassable.
+    pub maximum_slope: f64,
+    /// Fixed cost of crossing a river, per unit of river width.
+    pub river_crossing_cost: f64,
+    /// Rivers wider than this can't be bridged.
+    pub maximum_bridge_width: f64,
+    /// Particles below the sea level are impassable.
+    pub sea_level: f64,
+    /// Cost multiplier for travelling along existing roads, so that roads get shared.
+    pub road_reuse_factor: f64,
+}
+
+impl Default for RoadParams {
+    fn default() -> Self {
+        Self {
+            slope_cost: 20.0,
+            maximum_slope: 1.0,
+            river_crossing_cost: 5.0,
+            maximum_bridge_width: f64::MAX,
+            sea_level: 0.0,
+            road_reuse_factor: 0.5,
+        }
+    }
+}
+
+/// Place where a road crosses a river.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bridge {
+    pub from: Particle,
+    pub to: Particle,
+    pub site: (f64, f64),
+    /// The crossed river reach.
+    pub river: Particle,
+    pub river_width: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Road {
+    pub path: Vec<Particle>,
+    pub bridges: Vec<Bridge>,
+    pub cost: f64,
+}
+
+struct Step {
+    cost: f64,
+    bridge: Option<Bridge>,
+}
+
+/// Least-cost routing over the Voronoi neighbor graph of an elevation map.
+pub struct RoadPlanner<'a> {
+    elevation_map: &'a ParticleMap<f64>,
+    drainage_map: &'a DrainageMap,
+    voronoi: VoronoiCache,
+    params: RoadParams,
+}
+
+impl<'a> RoadPlanner<'a> {
+    pub fn new(
+        elevation_map: &'a ParticleMap<f64>,
+        drainage_map: &'a DrainageMap,
+        params: RoadParams,
+    ) -> Self {
+        Self {
+            elevation_map,
+            drainage_map,
+            voronoi: drainage_map.voronoi().clone(),
+            params,
+        }
+    }
+
+    fn step(&self, from: &Particle, to: &Particle) -> Option<Step> {
+        let from_elevation = *self.elevation_map.get(from)?;
+        let to_elevation = *self.elevation_map.get(to)?;
+        if to_elevation < self.params.sea_level {
+            return None;
+        }
+        let (from_site, to_site) = (from.site(), to.site());
+        let distance = (from_site.0 - to_site.0).hypot(from_site.1 - to_site.1);
+        let slope = ((to_elevation - from_elevation) / distance).abs();
+        if slope > self.params.maximum_slope {
+            return None;
+        }
+        let mut cost = distance * (1.0 + self.params.slope_cost * slope);
+
+        let bridge = match self.drainage_map.river_across(from_site, to_site) {
+            Some((river, site)) => {
+                let river_width = self.drainage_map.river_width(river);
+                if river_width > self.params.maximum_bridge_width {
+                    return None;
+                }
+                cost += self.params.river_crossing_cost * river_width;
+                Some(Bridge {
+                    from: *from,
+                    to: *to,
+                    site,
+                    river: river.particle,
+                    river_width,
+                })
+            }
+            None => None,
+        };
+
+        Some(Step { cost, bridge })
+    }
+
+    /// Cheapest road from `from` to the nearest particle of `targets`. Steps between two
+    /// particles of `roads` are discounted by the reuse factor.
+    pub fn route(
+        &self,
+        from: Particle,
+        targets: &HashSet<Particle>,
+        roads: &HashSet<Particle>,
+    ) -> Option<Road> {
+        let mut settled = HashSet::new();
+        let mut tentative = HashMap::from([(from, 0.0)]);
+        let mut previous: HashMap<Particle, (Particle, Option<Bridge>)> = HashMap::new();
+        let mut open = BinaryHeap::from([LowestFirst {
+            key: 0.0,
+            particle: from,
+        }]);
+
+        while let Some(LowestFirst { key, particle }) = open.pop() {
+            if !settled.insert(particle) {
+                continue;
+            }
+
+            if targets.contains(&particle) {
+                let mut path = vec![particle];
+                let mut bridges = Vec::new();
+                let mut current = particle;
+                while let Some((before, bridge)) = previous.get(&current) {
+                    path.push(*before);
+                    bridges.extend(*bridge);
+                    current = *before;
+                }
+                path.reverse();
+                bridges.reverse();
+                return Some(Road {
+                    path,
+                    bridges,
+                    cost: key,
+                });
+            }
+
+            for neighbor in self.voronoi.get_or_compute(&particle).neighbors.iter() {
+                if settled.contains(neighbor) {
+                    continue;
+                }
+                let Some(step) = self.step(&particle, neighbor) else {
+                    continue;
+                };
+                let cost = if roads.contains(&particle) && roads.contains(neighbor) {
+                    step.cost * self.params.road_reuse_factor
+                } else {
+                    step.cost
+                };
+                let total = key + cost;
+                if tentative.get(neighbor).is_none_or(|known| total < *known) {
+                    tentative.insert(*neighbor, total);
+                    previous.insert(*neighbor, (particle, step.bridge));
+                    open.push(LowestFirst {
+                        key: total,
+                        particle: *neighbor,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Connects the settlements, best ranked first, each to the cheapest settlement
+    /// connected so far. Settlements that can't be reached are left unconnected.
+    pub fn connect(&self, settlements: &[Settlement]) -> Vec<Road> {
+        let mut settlements = settlements.iter().collect::<Vec<_>>();
+        settlements.sort_by_key(|settlement| settlement.rank);
+
+        let mut connected = HashSet::new();
+        let mut network = HashSet::new();
+        let mut roads = Vec::new();
+        for settlement in settlements {
+            if !connected.is_empty() {
+                let Some(road) = self.route(settlement.particle, &connected, &network) else {
+                    continue;
+                };
+                network.extend(road.path.iter().copied());
+                roads.push(road);
+            }
+            connected.insert(settlement.particle);
+        }
+        roads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drainage::{
+            builder::DrainageMapBuilder,
+            node::{midpoint, DrainageBasinNode},
+        },
+        test_util::{particle_at, terrain},
+    };
+
+    /// Valley along the x axis, falling towards x = 0, with its floor at y = 2.5.
+    fn valley() -> ParticleMap<f64> {
+        terrain(8.0, 5.0, |x, y| x * 0.02 + (y - 2.5).abs() * 0.1 + 0.1)
+    }
+
+    /// Drainage map where only the valley floor carries a river, narrow next to the spacing.
+    fn floor_river(elevation_map: &ParticleMap<f64>) -> DrainageMap {
+        let all = DrainageMapBuilder::new(elevation_map)
+            .river_strength(0.01)
+            .build()
+            .unwrap();
+        let scale = elevation_map.params().scale;
+        let on_floor =
+            |node: &&DrainageBasinNode| (node.particle.site().1 / scale - 2.5).abs() < 1e-9;
+        let widths = |floor: bool| {
+            all.map()
+                .iter()
+                .filter(move |(_, node)| on_floor(node) == floor)
+                .map(|(_, node)| all.river_width(node))
+        };
+        let slopes = widths(false).fold(f64::MIN, f64::max);
+        let floor = widths(true).fold(f64::MAX, f64::min);
+        assert!(slopes < floor);
+        DrainageMapBuilder::new(elevation_map)
+            .river_strength(0.01)
+            .river_ignoreable_width_strength((slopes + floor) / 2.0 / scale)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn steps_onto_a_river_bridge_it() {
+        let elevation_map = valley();
+        let drainage_map = floor_river(&elevation_map);
+        let planner = RoadPlanner::new(&elevation_map, &drainage_map, RoadParams::default());
+
+        let (bank, floor) = (
+            particle_at(&elevation_map, 3.5, 1.5),
+            particle_at(&elevation_map, 3.5, 2.5),
+        );
+        let middle = midpoint(bank.site(), floor.site());
+        // The river is too narrow to reach the middle of the step.
+        assert!(drainage_map.river_at(middle.0, middle.1).is_none());
+
+        let step = planner.step(&bank, &floor).unwrap();
+        let bridge = step.bridge.unwrap();
+        let river = drainage_map.map().get(&bridge.river).unwrap();
+        assert!(drainage_map.is_river(river));
+        assert!(river
+            .main_river
+            .collides(bridge.site.0, bridge.site.1, bridge.river_width));
+        let (from, to) = (bank.site(), floor.site());
+        let length = (to.0 - from.0).hypot(to.1 - from.1);
+        assert!(step.cost > length);
+
+        let dry = planner
+            .step(&particle_at(&elevation_map, 3.5, 0.5), &bank)
+            .unwrap();
+        assert!(dry.bridge.is_none());
+    }
+
+    #[test]
+    fn roads_detour_around_rivers_too_wide_to_bridge() {
+        let elevation_map = valley();
+        let drainage_map = floor_river(&elevation_map);
+        let (north, south) = (
+            particle_at(&elevation_map, 4.5, 0.5),
+            particle_at(&elevation_map, 4.5, 4.5),
+        );
+
+        let planner = RoadPlanner::new(&elevation_map, &drainage_map, RoadParams::default());
+        let road = planner
+            .route(north, &HashSet::from([south]), &HashSet::new())
+            .unwrap();
+        assert_eq!(road.path.first(), Some(&north));
+        assert_eq!(road.path.last(), Some(&south));
+        assert!(!road.bridges.is_empty());
+        for pair in road.path.windows(2) {
+            assert!(planner
+                .voronoi
+                .get_or_compute(&pair[0])
+                .neighbors
+                .contains(&pair[1]));
+        }
+
+        let unbridgeable = RoadPlanner::new(
+            &elevation_map,
+            &drainage_map,
+            RoadParams {
+                maximum_bridge_width: 0.0,
+                ..Default::default()
+            },
+        );
+        // The detour around the head of the river avoids every bridge.
+        let detour = unbridgeable
+            .route(north, &HashSet::from([south]), &HashSet::new())
+            .unwrap();
+        assert!(detour.bridges.is_empty());
+        assert!(detour.cost > road.cost);
+        for pair in detour.path.windows(2) {
+            let (from, to) = (pair[0].site(), pair[1].site());
+            assert!(drainage_map.river_across(from, to).is_none());
+        }
+    }
+
+    #[test]
+    fn settlements_connect_into_a_network() {
+        let elevation_map = terrain(6.0, 6.0, |x, y| (x + y) * 0.01 + 0.1);
+        let drainage_map = DrainageMapBuilder::new(&elevation_map)
+            .river_ignoreable_width_strength(f64::MAX)
+            .build()
+            .unwrap();
+        let planner = RoadPlanner::new(&elevation_map, &drainage_map, RoadParams::default());
+        let settlement = |rank: usize, x: f64, y: f64| Settlement {
+            particle: particle_at(&elevation_map, x, y),
+            rank,
+            score: 1.0,
+            reasons: Vec::new(),
+        };
+        let settlements = [
+            settlement(2, 5.5, 0.5),
+            settlement(0, 0.5, 0.5),
+            settlement(1, 0.5, 5.5),
+        ];
+
+        let roads = planner.connect(&settlements);
+        assert_eq!(roads.len(), 2);
+        assert_eq!(roads[0].path.first(), Some(&settlements[2].particle));
+        assert_eq!(roads[0].path.last(), Some(&settlements[1].particle));
+        assert_eq!(roads[1].path.first(), Some(&settlements[0].particle));
+        assert!(roads.iter().all(|road| road.bridges.is_empty()));
+    }
+}
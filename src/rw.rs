@@ -0,0 +1,45 @@
+use std::{error::Error, fs};
+
+use worley_particle::map::{rw::ParticleMapAttributeRW, ParticleMap};
+
+use crate::drainage::map::compare_sites;
+
+/// Writes the map like `ParticleMap::write_to_file`, with its entries in the order of
+/// their sites so that the same map always gives the same file.
+///
+/// The header, which holds the parameters of the map, is the one `write_to_file` writes
+/// for a copy of the map holding only its first entry.
+pub(crate) fn write_sorted_to_file<T: ParticleMapAttributeRW + Clone>(
+    particle_map: &ParticleMap<T>,
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut entries = particle_map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| compare_sites(a.0, b.0));
+    let Some((first, value)) = entries.first() else {
+        return particle_map.write_to_file(file_path);
+    };
+
+    std::iter::once((**first, (*value).clone()))
+        .collect::<ParticleMap<T>>()
+        .write_to_file(file_path)?;
+    let written = fs::read_to_string(file_path)?;
+    let mut lines = written.lines().collect::<Vec<_>>();
+    lines.pop();
+
+    let mut contents = lines
+        .into_iter()
+        .map(str::to_string)
+        .chain(entries.iter().map(|(particle, value)| {
+            particle
+                .to_strings()
+                .into_iter()
+                .chain(value.to_strings())
+                .collect::<Vec<_>>()
+                .join(",")
+        }))
+        .collect::<Vec<_>>()
+        .join("\n");
+    contents.push('\n');
+    fs::write(file_path, contents)?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::{collections::HashSet, sync::Arc};
+
+use worley_particle::{map::ParticleMap, Particle};
+
+/// Particles of an elevation map lying below the sea level.
+///
+/// Cloning is cheap: clones share the same set.
+#[derive(Debug, Clone)]
+pub struct SeaMask {
+    sea_level: f64,
+    sea: Arc<HashSet<Particle>>,
+}
+
+impl SeaMask {
+    pub fn new(elevation_map: &ParticleMap<f64>, sea_level: f64) -> Self {
+        let sea = elevation_map
+            .iter()
+            .filter(|(_, elevation)| **elevation < sea_level)
+            .map(|(particle, _)| *particle)
+            .collect::<HashSet<_>>();
+        Self {
+            sea_level,
+            sea: Arc::new(sea),
+        }
+    }
+
+    /// Mask of the given particles, for when the elevations are not at hand.
+    pub fn from_particles(particles: impl IntoIterator<Item = Particle>, sea_level: f64) -> Self {
+        Self {
+            sea_level,
+            sea: Arc::new(particles.into_iter().collect()),
+        }
+    }
+
+    pub fn sea_level(&self) -> f64 {
+        self.sea_level
+    }
+
+    pub fn is_sea(&self, particle: &Particle) -> bool {
+        self.sea.contains(particle)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Particle> {
+        self.sea.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sea.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sea.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::terrain;
+
+    #[test]
+    fn particles_strictly_below_the_sea_level_are_sea() {
+        let elevation_map = terrain(4.0, 4.0, |x, _| x);
+        let sea_mask = SeaMask::new(&elevation_map, 1.5);
+        assert_eq!(sea_mask.sea_level(), 1.5);
+        assert_eq!(sea_mask.len(), 4);
+        for (particle, elevation) in elevation_map.iter() {
+            assert_eq!(sea_mask.is_sea(particle), *elevation < 1.5);
+        }
+        assert!(sea_mask
+            .iter()
+            .all(|particle| elevation_map.get(particle) == Some(&0.5)));
+
+        let rebuilt = SeaMask::from_particles(sea_mask.iter().copied(), 1.5);
+        assert_eq!(*rebuilt.sea, *sea_mask.sea);
+        assert!(SeaMask::new(&elevation_map, 0.5).is_empty());
+    }
+
+    #[test]
+    fn clones_share_the_mask() {
+        let sea_mask = SeaMask::new(&terrain(4.0, 4.0, |x, _| x), 1.5);
+        assert!(Arc::ptr_eq(&sea_mask.sea, &sea_mask.clone().sea));
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{drainage::map::DrainageMap, flatness::FlatnessMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SettlementParams {
+    /// Maximum number of settlements to place.
+    pub count: usize,
+    /// Settlements are never placed closer to each other than this.
+    pub minimum_distance: f64,
+    /// Particles scoring lower than this, bonuses included, are never picked.
+    pub minimum_score: f64,
+    /// Added to the score of particles next to a river confluence.
+    pub confluence_bonus: f64,
+    /// Added to the score of particles next to a river mouth.
+    pub mouth_bonus: f64,
+    /// Added to the score of particles next to a river, per stream order.
+    pub river_order_bonus: f64,
+}
+
+impl Default for SettlementParams {
+    fn default() -> Self {
+        Self {
+            count: 10,
+            minimum_distance: 0.1,
+            minimum_score: 0.3,
+            confluence_bonus: 0.2,
+            mouth_bonus: 0.3,
+            river_order_bonus: 0.05,
+        }
+    }
+}
+
+/// Why a site was picked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlacementReason {
+    Suitability(f64),
+    Flatness(f64),
+    /// The site is on or next to a river of this stream order.
+    NearRiver {
+        order: usize,
+    },
+    /// The site is on or next to where rivers meet.
+    Confluence {
+        order: usize,
+    },
+    /// The site is on or next to where a river leaves the map through an open edge or
+    /// reaches the sea. See [`DrainageMap::is_mouth`].
+    RiverMouth {
+        order: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settlement {
+    pub particle: Particle,
+    /// Zero for the best site.
+    pub rank: usize,
+    pub score: f64,
+    pub reasons: Vec<PlacementReason>,
+}
+
+impl Settlement {
+    pub fn site(&self) -> (f64, f64) {
+        self.particle.site()
+    }
+}
+
+/// Picks up to `params.count` settlement sites from `suitability`, best first.
+///
+/// Every particle is scored by its suitability plus bonuses for the rivers around it, and
+/// sites are taken greedily by score, skipping particles within `minimum_distance` of an
+/// already placed settlement.
+pub fn place_settlements(
+    suitability: &ParticleMap<f64>,
+    drainage_map: &DrainageMap,
+    flatness_map: Option<&FlatnessMap>,
+    params: &SettlementParams,
+) -> Vec<Settlement> {
+    let orders = drainage_map.stream_orders();
+    let mut river_inflows: HashMap<Particle, usize> = HashMap::new();
+    for (particle, node) in drainage_map.map().iter() {
+        if !node.is_outlet() && orders.contains_key(particle) && orders.contains_key(&node.flow_to)
+        {
+            *river_inflows.entry(node.flow_to).or_default() += 1;
+        }
+    }
+
+    let mut candidates = suitability
+        .iter()
+        .map(|(particle, suitability)| {
+            let mut reasons = vec![PlacementReason::Suitability(*suitability)];
+            let mut score = *suitability;
+
+            if let Some(flatness) = flatness_map.and_then(|map| map.map().get(particle)) {
+                reasons.push(PlacementReason::Flatness(*flatness));
+            }
+
+            let surroundings = std::iter::once(*particle)
+                .chain(
+                    drainage_map
+                        .voronoi()
+                        .get_or_compute(particle)
+                        .neighbors
+                        .clone(),
+                )
+                .filter_map(|particle| Some((particle, *orders.get(&particle)?)))
+                .collect::<Vec<_>>();
+
+            if let Some(order) = surroundings.iter().map(|(_, order)| *order).max() {
+                reasons.push(PlacementReason::NearRiver { order });
+                score += params.river_order_bonus * order as f64;
+            }
+            let confluence = surroundings
+                .iter()
+                .filter(|(particle, _)| river_inflows.get(particle).is_some_and(|n| *n >= 2))
+                .map(|(_, order)| *order)
+                .max();
+            if let Some(order) = confluence {
+                reasons.push(PlacementReason::Confluence { order });
+                score += params.confluence_bonus;
+            }
+            let mouth = surroundings
+                .iter()
+                .filter(|(particle, _)| {
+                    drainage_map
+                        .map()
+                        .get(particle)
+                        .is_some_and(|node| drainage_map.is_mouth(node))
+                })
+                .map(|(_, order)| *order)
+                .max();
+            if let Some(order) = mouth {
+                reasons.push(PlacementReason::RiverMouth { order });
+                score += params.mouth_bonus;
+            }
+
+            (*particle, score, reasons)
+        })
+        .filter(|(_, score, _)| *score >= params.minimum_score)
+        .collect::<Vec<_>>();
+
+    candidates.sort_by(|a, b| {
+        let (site_a, site_b) = (a.0.site(), b.0.site());
+        b.1.total_cmp(&a.1)
+            .then(site_a.0.total_cmp(&site_b.0))
+            .then(site_a.1.total_cmp(&site_b.1))
+    });
+
+    let mut settlements: Vec<Settlement> = Vec::new();
+    for (particle, score, reasons) in candidates {
+        if settlements.len() >= params.count {
+            break;
+        }
+        let site = particle.site();
+        let crowded = settlements.iter().any(|settlement| {
+            let other = settlement.site();
+            (site.0 - other.0).hypot(site.1 - other.1) < params.minimum_distance
+        });
+        if crowded {
+            continue;
+        }
+        settlements.push(Settlement {
+            particle,
+            rank: settlements.len(),
+            score,
+            reasons,
+        });
+    }
+
+    settlements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boundary::BoundaryPolicy, drainage::builder::DrainageMapBuilder, test_util::terrain,
+    };
+
+    /// Valley along the x axis, falling towards x = 0.
+    fn valley() -> ParticleMap<f64> {
+        terrain(8.0, 5.0, |x, y| x * 0.1 + (y - 2.5).abs() * 0.2 + 0.1)
+    }
+
+    fn uniform(elevation_map: &ParticleMap<f64>, value: f64) -> ParticleMap<f64> {
+        elevation_map
+            .iter()
+            .map(|(particle, _)| (*particle, value))
+            .collect()
+    }
+
+    fn everywhere() -> SettlementParams {
+        SettlementParams {
+            count: usize::MAX,
+            minimum_distance: 0.0,
+            minimum_score: 0.0,
+            ..Default::default()
+        }
+    }
+
+    fn has_mouth(settlement: &Settlement) -> bool {
+        settlement
+            .reasons
+            .iter()
+            .any(|reason| matches!(reason, PlacementReason::RiverMouth { .. }))
+    }
+
+    #[test]
+    fn mouths_are_where_water_leaves_the_map() {
+        let elevation_map = valley();
+        let suitability = uniform(&elevation_map, 0.5);
+        let build = |builder: DrainageMapBuilder| {
+            let drainage_map = builder
+                .river_ignoreable_width_strength(0.0)
+                .build()
+                .unwrap();
+            place_settlements(&suitability, &drainage_map, None, &everywhere())
+        };
+
+        // The lowest particle of a closed map is a sink, not a mouth.
+        let closed = build(DrainageMapBuilder::new(&elevation_map));
+        assert_eq!(closed.len(), elevation_map.iter().count());
+        assert!(!closed.iter().any(has_mouth));
+        assert!(closed.iter().any(|settlement| settlement
+            .reasons
+            .iter()
+            .any(|reason| matches!(reason, PlacementReason::Confluence { .. }))));
+
+        let scale = elevation_map.params().scale;
+        let open =
+            build(DrainageMapBuilder::new(&elevation_map).boundary_policy(BoundaryPolicy::Open));
+        assert!(open.iter().any(has_mouth));
+        for settlement in open.iter().filter(|settlement| has_mouth(settlement)) {
+            assert!(settlement.score >= 0.5 + everywhere().mouth_bonus);
+        }
+
+        let sea = build(DrainageMapBuilder::new(&elevation_map).sea_level(0.3));
+        assert!(sea.iter().any(has_mouth));
+        for settlement in sea.iter().filter(|settlement| has_mouth(settlement)) {
+            // On or next to the particles below the sea level, at the low end of the map.
+            assert!(settlement.site().0 < 3.0 * scale);
+        }
+    }
+
+    #[test]
+    fn settlements_are_ranked_by_score_and_kept_apart() {
+        let elevation_map = valley();
+        let scale = elevation_map.params().scale;
+        let suitability = elevation_map
+            .iter()
+            .map(|(particle, elevation)| (*particle, 1.0 - *elevation))
+            .collect::<ParticleMap<f64>>();
+        let drainage_map = DrainageMapBuilder::new(&elevation_map)
+            .river_ignoreable_width_strength(f64::MAX)
+            .build()
+            .unwrap();
+        let params = SettlementParams {
+            count: 3,
+            minimum_distance: 2.5 * scale,
+            minimum_score: 0.2,
+            ..Default::default()
+        };
+
+        let settlements = place_settlements(&suitability, &drainage_map, None, &params);
+        assert_eq!(settlements.len(), 3);
+        let best = suitability
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::MIN, f64::max);
+        assert_eq!(settlements[0].score, best);
+        for (rank, settlement) in settlements.iter().enumerate() {
+            assert_eq!(settlement.rank, rank);
+            assert_eq!(
+                settlement.reasons,
+                vec![PlacementReason::Suitability(settlement.score)]
+            );
+            assert!(settlement.score >= params.minimum_score);
+            for other in &settlements[..rank] {
+                assert!(other.score >= settlement.score);
+                let (a, b) = (settlement.site(), other.site());
+                assert!((a.0 - b.0).hypot(a.1 - b.1) >= params.minimum_distance);
+            }
+        }
+
+        let picky = SettlementParams {
+            minimum_score: best + 1e-9,
+            ..params
+        };
+        assert!(place_settlements(&suitability, &drainage_map, None, &picky).is_empty());
+    }
+}
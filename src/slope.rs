@@ -0,0 +1,102 @@
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{boundary::Boundary, voronoi::VoronoiCache};
+
+/// Elevation gradient at a particle: the rise per unit distance along each axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlopeVector {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl SlopeVector {
+    pub fn magnitude(&self) -> f64 {
+        self.x.hypot(self.y)
+    }
+
+    /// Direction the terrain faces, i.e. the steepest way down, in radians
+    /// counterclockwise from the x axis. `None` on perfectly flat terrain.
+    pub fn aspect(&self) -> Option<f64> {
+        if self.x == 0.0 && self.y == 0.0 {
+            return None;
+        }
+        Some((-self.y).atan2(-self.x))
+    }
+}
+
+/// Fits a plane through the particle and its Voronoi neighbors by least squares.
+///
+/// Returns `None` when the neighbors are too few or collinear to fit a plane.
+pub(crate) fn estimate_slope<T>(
+    elevation_map: &ParticleMap<f64>,
+    voronoi: &VoronoiCache,
+    boundary: &Boundary<T>,
+    particle: &Particle,
+) -> Option<SlopeVector> {
+    let elevation = *elevation_map.get(particle)?;
+    let site = particle.site();
+    let neighbors = boundary.resolve(particle, &voronoi.get_or_compute(particle).neighbors);
+
+    let (mut sxx, mut sxy, mut syy, mut sxz, mut syz) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (neighbor, neighbor_site) in neighbors.inside {
+        let Some(neighbor_elevation) = elevation_map.get(&neighbor) else {
+            continue;
+        };
+        let (dx, dy) = (neighbor_site.0 - site.0, neighbor_site.1 - site.1);
+        let dz = neighbor_elevation - elevation;
+        sxx += dx * dx;
+        sxy += dx * dy;
+        syy += dy * dy;
+        sxz += dx * dz;
+        syz += dy * dz;
+    }
+
+    let determinant = sxx * syy - sxy * sxy;
+    if determinant.abs() <= f64::EPSILON * (sxx * syy).abs() {
+        return None;
+    }
+    Some(SlopeVector {
+        x: (sxz * syy - syz * sxy) / determinant,
+        y: (syz * sxx - sxz * sxy) / determinant,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boundary::BoundaryPolicy, test_util::terrain};
+
+    fn slopes(elevation_map: &ParticleMap<f64>) -> Vec<Option<SlopeVector>> {
+        let voronoi = VoronoiCache::new(elevation_map);
+        let boundary = Boundary::new(elevation_map, BoundaryPolicy::Closed);
+        elevation_map
+            .iter()
+            .map(|(particle, _)| estimate_slope(elevation_map, &voronoi, &boundary, particle))
+            .collect()
+    }
+
+    #[test]
+    fn planes_give_their_gradient() {
+        let scale = terrain(1.0, 1.0, |_, _| 0.0).params().scale;
+        let elevation_map = terrain(5.0, 5.0, |x, y| (0.3 * x - 0.2 * y) * scale);
+        for slope in slopes(&elevation_map) {
+            let slope = slope.unwrap();
+            assert!((slope.x - 0.3).abs() < 1e-9 && (slope.y + 0.2).abs() < 1e-9);
+            // Downhill is towards -x and +y.
+            let aspect = slope.aspect().unwrap();
+            assert!((aspect - 0.2f64.atan2(-0.3)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn flat_and_degenerate_neighborhoods() {
+        let flat = terrain(4.0, 4.0, |_, _| 1.0);
+        for slope in slopes(&flat) {
+            assert_eq!(slope.unwrap().magnitude(), 0.0);
+            assert_eq!(slope.unwrap().aspect(), None);
+        }
+        // A single row has only collinear neighbors.
+        let row = terrain(4.0, 1.0, |x, _| x);
+        assert!(slopes(&row).iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use worley_particle::{map::ParticleMap, Particle};
+
+use crate::{
+    drainage::map::DrainageMap, flatness::FlatnessMap, rw::write_sorted_to_file,
+    voronoi::VoronoiCache,
+};
+
+/// Weights and ranges of the terms making up the suitability score. A term with a zero
+/// weight is ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SuitabilityParams {
+    pub flatness_weight: f64,
+    pub river_weight: f64,
+    /// Distance at which the river term has fallen to `1 / e`.
+    pub river_distance: f64,
+    pub elevation_weight: f64,
+    /// Elevations scoring full marks on the elevation term; the score falls off linearly
+    /// over `elevation_falloff` outside of it.
+    pub elevation_band: (f64, f64),
+    pub elevation_falloff: f64,
+    pub coast_weight: f64,
+    /// Distance at which the coast term has fallen to `1 / e`.
+    pub coast_distance: f64,
+}
+
+impl Default for SuitabilityParams {
+    fn default() -> Self {
+        Self {
+            flatness_weight: 1.0,
+            river_weight: 1.0,
+            river_distance: 0.05,
+            elevation_weight: 0.5,
+            elevation_band: (0.0, 0.3),
+            elevation_falloff: 0.1,
+            coast_weight: 0.5,
+            coast_distance: 0.1,
+        }
+    }
+}
+
+impl SuitabilityParams {
+    fn elevation_score(&self, elevation: f64) -> f64 {
+        let (low, high) = self.elevation_band;
+        let outside = (low - elevation).max(elevation - high).max(0.0);
+        if self.elevation_falloff <= 0.0 {
+            return if outside > 0.0 { 0.0 } else { 1.0 };
+        }
+        (1.0 - outside / self.elevation_falloff).max(0.0)
+    }
+}
+
+/// Score in `[0, 1]` of how well each land particle suits a settlement. The sea is taken
+/// from the sea mask of the flatness map: sea particles get no score and form the coast.
+pub struct SuitabilityMap {
+    pub particle_map: ParticleMap<f64>,
+    voronoi: VoronoiCache,
+}
+
+impl SuitabilityMap {
+    /// `layers` are additional `(score, weight)` maps; particles missing from a layer
+    /// score zero on it.
+    pub fn new(
+        elevation_map: &ParticleMap<f64>,
+        flatness_map: &FlatnessMap,
+        drainage_map: &DrainageMap,
+        params: &SuitabilityParams,
+        layers: &[(&ParticleMap<f64>, f64)],
+    ) -> Self {
+        let voronoi = drainage_map.voronoi().clone();
+        let on_map = |particle: &Particle| elevation_map.get(particle).is_some();
+
+        let river_distances = voronoi.graph_distances(
+            drainage_map
+                .map()
+                .iter()
+                .filter(|(_, node)| drainage_map.is_river(node))
+                .map(|(particle, _)| *particle),
+            on_map,
+        );
+        let sea_mask = flatness_map.sea_mask();
+        let coast_distances = voronoi.graph_distances(sea_mask.iter().copied(), on_map);
+
+        let total_weight = params.flatness_weight
+            + params.river_weight
+            + params.elevation_weight
+            + params.coast_weight
+            + layers.iter().map(|(_, weight)| weight).sum::<f64>();
+
+        let elevations = elevation_map.iter().collect::<Vec<_>>();
+
+        let elevations_iter = par_iter!(elevations);
+
+        let particle_map = elevations_iter
+            .filter(|(particle, _)| !sea_mask.is_sea(particle) && total_weight > 0.0)
+            .map(|&(particle, elevation)| {
+                let proximity = |distances: &HashMap<Particle, f64>, scale: f64| {
+                    distances
+                        .get(particle)
+                        .map_or(0.0, |distance| (-distance / scale).exp())
+                };
+                let score = params.flatness_weight
+                    * flatness_map.map().get(particle).copied().unwrap_or(0.0)
+                    + params.river_weight * proximity(&river_distances, params.river_distance)
+                    + params.elevation_weight * params.elevation_score(*elevation)
+                    + params.coast_weight * proximity(&coast_distances, params.coast_distance)
+                    + layers
+                        .iter()
+                        .map(|(layer, weight)| weight * layer.get(particle).copied().unwrap_or(0.0))
+                        .sum::<f64>();
+                (*particle, (score / total_weight).clamp(0.0, 1.0))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect::<ParticleMap<f64>>();
+
+        Self {
+            particle_map,
+            voronoi,
+        }
+    }
+
+    pub fn save_to_file(&self, file_path: &str) {
+        write_sorted_to_file(&self.particle_map, file_path).expect("Error writing suitability map");
+    }
+
+    pub fn load_from_file(file_path: &str) -> Option<Self> {
+        let particle_map = ParticleMap::<f64>::read_from_file(file_path).ok()?;
+        Some(Self {
+            particle_map,
+            voronoi: VoronoiCache::default(),
+        })
+    }
+
+    pub fn map(&self) -> &ParticleMap<f64> {
+        &self.particle_map
+    }
+
+    pub fn voronoi(&self) -> &VoronoiCache {
+        &self.voronoi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        drainage::builder::DrainageMapBuilder,
+        flatness::{FlatnessMapBuilder, FlatnessMetric},
+        test_util::{particle_at, terrain},
+    };
+
+    #[test]
+    fn elevation_score_falls_off_outside_the_band() {
+        let params = SuitabilityParams {
+            elevation_band: (0.2, 0.4),
+            elevation_falloff: 0.1,
+            ..Default::default()
+        };
+        assert_eq!(params.elevation_score(0.3), 1.0);
+        assert!((params.elevation_score(0.45) - 0.5).abs() < 1e-9);
+        assert!((params.elevation_score(0.15) - 0.5).abs() < 1e-9);
+        assert_eq!(params.elevation_score(0.6), 0.0);
+
+        let hard = SuitabilityParams {
+            elevation_falloff: 0.0,
+            ..params
+        };
+        assert_eq!(hard.elevation_score(0.4), 1.0);
+        assert_eq!(hard.elevation_score(0.41), 0.0);
+    }
+
+    /// Parameters scoring nothing but the term whose weight `with` sets.
+    fn only(with: impl FnOnce(&mut SuitabilityParams)) -> SuitabilityParams {
+        let mut params = SuitabilityParams {
+            flatness_weight: 0.0,
+            river_weight: 0.0,
+            elevation_weight: 0.0,
+            coast_weight: 0.0,
+            ..Default::default()
+        };
+        with(&mut params);
+        params
+    }
+
+    fn score_at(suitability: &SuitabilityMap, x: f64, y: f64) -> f64 {
+        let particle = particle_at(suitability.map(), x, y);
+        *suitability.map().get(&particle).unwrap()
+    }
+
+    #[test]
+    fn scores_average_the_weighted_terms_on_land() {
+        let elevation_map = terrain(6.0, 6.0, |x, y| (x + y) * 0.05);
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map)
+            .sea_level(0.2)
+            .build(|_| None);
+        let drainage_map = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        let layer = elevation_map
+            .iter()
+            .map(|(particle, elevation)| (*particle, *elevation))
+            .collect::<ParticleMap<f64>>();
+        let params = only(|params| {
+            params.elevation_weight = 1.0;
+            params.elevation_band = (-1.0, 2.0);
+        });
+
+        let suitability = SuitabilityMap::new(
+            &elevation_map,
+            &flatness_map,
+            &drainage_map,
+            &params,
+            &[(&layer, 1.0)],
+        );
+        for (particle, elevation) in elevation_map.iter() {
+            match suitability.map().get(particle) {
+                Some(score) => {
+                    assert!(*elevation >= 0.2);
+                    assert!((score - (1.0 + elevation) / 2.0).abs() < 1e-9);
+                }
+                None => assert!(*elevation < 0.2),
+            }
+        }
+    }
+
+    #[test]
+    fn flat_land_scores_on_the_flatness_term() {
+        // Flat for x < 3, rising steeply beyond.
+        let elevation_map = terrain(6.0, 4.0, |x, _| (x - 3.0).max(0.0));
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map)
+            .metric(FlatnessMetric::SlopeMagnitude)
+            .build(|slope| (slope < 0.5).then_some(1.0 - slope));
+        let drainage_map = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        let params = only(|params| params.flatness_weight = 1.0);
+
+        let suitability =
+            SuitabilityMap::new(&elevation_map, &flatness_map, &drainage_map, &params, &[]);
+        assert_eq!(suitability.map().iter().count(), 24);
+        for (particle, score) in suitability.map().iter() {
+            let flatness = flatness_map.map().get(particle).copied().unwrap_or(0.0);
+            assert!((score - flatness).abs() < 1e-9);
+        }
+        assert_eq!(score_at(&suitability, 0.5, 1.5), 1.0);
+        assert_eq!(score_at(&suitability, 5.5, 1.5), 0.0);
+    }
+
+    #[test]
+    fn land_near_rivers_scores_on_the_river_term() {
+        // Every row drains along itself towards x = 0, carrying a river where it has
+        // drained at least four particles, that is for x < 5.
+        let elevation_map = terrain(8.0, 5.0, |x, y| x * 0.1 + y * 0.01);
+        let scale = elevation_map.params().scale;
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map).build(|_| None);
+        let drainage_map = DrainageMapBuilder::new(&elevation_map)
+            .river_ignoreable_width_strength(2.0)
+            .build()
+            .unwrap();
+        let params = only(|params| {
+            params.river_weight = 1.0;
+            params.river_distance = scale;
+        });
+
+        let suitability =
+            SuitabilityMap::new(&elevation_map, &flatness_map, &drainage_map, &params, &[]);
+        for (particle, score) in suitability.map().iter() {
+            let node = drainage_map.map().get(particle).unwrap();
+            assert_eq!(*score == 1.0, drainage_map.is_river(node));
+        }
+        let (near, far) = (
+            score_at(&suitability, 5.5, 2.5),
+            score_at(&suitability, 7.5, 2.5),
+        );
+        assert!(1.0 > near && near > far && far > 0.0);
+    }
+
+    #[test]
+    fn land_near_the_sea_scores_on_the_coast_term() {
+        let elevation_map = terrain(6.0, 4.0, |x, _| x * 0.1);
+        let scale = elevation_map.params().scale;
+        // Only the flatness map knows the sea level, putting the column at x = 0.5 in the
+        // sea.
+        let flatness_map = FlatnessMapBuilder::new(&elevation_map)
+            .sea_level(0.1)
+            .build(|_| None);
+        let drainage_map = DrainageMapBuilder::new(&elevation_map).build().unwrap();
+        let params = only(|params| {
+            params.coast_weight = 1.0;
+            params.coast_distance = scale;
+        });
+
+        let suitability =
+            SuitabilityMap::new(&elevation_map, &flatness_map, &drainage_map, &params, &[]);
+        assert_eq!(suitability.map().iter().count(), 20);
+        for y in [0.5, 1.5, 2.5, 3.5] {
+            let scores = [1.5, 2.5, 3.5, 4.5, 5.5].map(|x| score_at(&suitability, x, y));
+            assert!(scores.windows(2).all(|pair| pair[0] > pair[1]));
+            assert!(scores[4] > 0.0);
+        }
+    }
+}
+
+#[cfg(feature = "visualize")]
+mod visualization {
+    use gtk4::{cairo::Context, DrawingArea};
+    use vislayers::{geometry::FocusRange, window::Layer};
+
+    use super::SuitabilityMap;
+
+    impl Layer for SuitabilityMap {
+        fn draw(&self, drawing_area: &DrawingArea, ctx: &Context, focus_range: &FocusRange) {
+            self.voronoi.fill_cells(
+                drawing_area,
+                ctx,
+                focus_range,
+                self.particle_map.iter(),
+                |suitability| [0.8, 0.1, 0.6, *suitability],
+            );
+        }
+    }
+}
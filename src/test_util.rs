@@ -0,0 +1,90 @@
+use worley_particle::{map::ParticleMap, Particle, ParticleParameters};
+
+use crate::drainage::{
+    map::DrainageMap,
+    node::{DrainageBasinNode, Stream},
+};
+
+/// Particles with their sites in `[0, width) x [0, height)`, measured in particle spacings.
+pub(crate) fn particles(width: f64, height: f64) -> Vec<Particle> {
+    let params = ParticleParameters::default();
+    let (width, height) = (width * params.scale, height * params.scale);
+    Particle::from_inside_radius(
+        width / 2.0,
+        height / 2.0,
+        params,
+        width.hypot(height) / 2.0 + params.scale,
+    )
+    .into_iter()
+    .filter(|particle| {
+        let site = particle.site();
+        (0.0..width).contains(&site.0) && (0.0..height).contains(&site.1)
+    })
+    .collect()
+}
+
+/// Elevation map over [`particles`], with `elevation` taking sites in particle spacings.
+pub(crate) fn terrain(
+    width: f64,
+    height: f64,
+    elevation: impl Fn(f64, f64) -> f64,
+) -> ParticleMap<f64> {
+    let scale = ParticleParameters::default().scale;
+    particles(width, height)
+        .into_iter()
+        .map(|particle| {
+            let site = particle.site();
+            (particle, elevation(site.0 / scale, site.1 / scale))
+        })
+        .collect()
+}
+
+/// Particle of `map` at the site `(x, y)`, measured in particle spacings.
+pub(crate) fn particle_at<T>(map: &ParticleMap<T>, x: f64, y: f64) -> Particle {
+    let scale = map.params().scale;
+    map.iter()
+        .map(|(particle, _)| *particle)
+        .find(|particle| {
+            let site = particle.site();
+            (site.0 / scale - x).abs() < 1e-9 && (site.1 / scale - y).abs() < 1e-9
+        })
+        .unwrap()
+}
+
+/// Outlet node with a straight stream two spacings long along the x axis.
+pub(crate) fn node(drainage_area: f64, slope: f64) -> DrainageBasinNode {
+    let particle = particles(1.0, 1.0)[0];
+    DrainageBasinNode {
+        particle,
+        elevation: 0.0,
+        area: 1.0,
+        drainage_area,
+        slope,
+        flow_to: particle,
+        main_river: Stream::new((0.0, 0.0), (1.0, 0.0), (2.0, 0.0)),
+    }
+}
+
+/// Asserts that both maps route every particle to the same receiver with the same
+/// drainage area.
+pub(crate) fn assert_same_drainage(expected: &DrainageMap, actual: &DrainageMap) {
+    assert_eq!(expected.map().iter().count(), actual.map().iter().count());
+    for (particle, node) in expected.map().iter() {
+        let other = actual.map().get(particle).unwrap();
+        assert_eq!(node.flow_to, other.flow_to);
+        assert!((node.drainage_area - other.drainage_area).abs() < 1e-9 * node.drainage_area);
+    }
+}
+
+/// Asserts that both maps draw the same stream for every particle.
+pub(crate) fn assert_same_streams(expected: &DrainageMap, actual: &DrainageMap) {
+    for (particle, node) in expected.map().iter() {
+        let other = actual.map().get(particle).unwrap();
+        assert_eq!(
+            node.main_river,
+            other.main_river,
+            "at {:?}",
+            particle.site()
+        );
+    }
+}
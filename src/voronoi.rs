@@ -0,0 +1,289 @@
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    sync::Arc,
+};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use worley_particle::{map::ParticleMap, Particle};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoronoiCell {
+    pub area: f64,
+    pub neighbors: Vec<Particle>,
+    pub polygon: Vec<(f64, f64)>,
+    /// Length of the edge shared with each neighbor, in the order of `neighbors`.
+    pub edge_lengths: Vec<f64>,
+}
+
+impl VoronoiCell {
+    pub fn new(particle: &Particle) -> Self {
+        let voronoi = particle.calculate_voronoi();
+        let area = voronoi.area();
+        let site = particle.site();
+        let edge_lengths = voronoi
+            .neighbors
+            .iter()
+            .map(|neighbor| shared_edge_length(site, neighbor.site(), &voronoi.polygon))
+            .collect();
+
+        Self {
+            area,
+            neighbors: voronoi.neighbors,
+            polygon: voronoi.polygon,
+            edge_lengths,
+        }
+    }
+}
+
+/// Finds the polygon edge lying on the bisector between `site` and `neighbor_site`.
+fn shared_edge_length(site: (f64, f64), neighbor_site: (f64, f64), polygon: &[(f64, f64)]) -> f64 {
+    let tolerance = (site.0 - neighbor_site.0).hypot(site.1 - neighbor_site.1) * 1e-3;
+    (0..polygon.len())
+        .map(|i| (polygon[i], polygon[(i + 1) % polygon.len()]))
+        .map(|(a, b)| {
+            let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+            let discrepancy = ((middle.0 - site.0).hypot(middle.1 - site.1)
+                - (middle.0 - neighbor_site.0).hypot(middle.1 - neighbor_site.1))
+            .abs();
+            (discrepancy, (a.0 - b.0).hypot(a.1 - b.1))
+        })
+        .filter(|(discrepancy, _)| *discrepancy < tolerance)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map_or(0.0, |(_, length)| length)
+}
+
+/// Voronoi cells of a set of particles, computed once and shared between builders.
+///
+/// Cloning is cheap: clones share the same cells.
+#[derive(Debug, Clone, Default)]
+pub struct VoronoiCache {
+    cells: Arc<HashMap<Particle, VoronoiCell>>,
+}
+
+impl VoronoiCache {
+    pub fn new<T>(particle_map: &ParticleMap<T>) -> Self {
+        Self::from_particles(particle_map.iter().map(|(particle, _)| *particle))
+    }
+
+    pub fn from_particles(particles: impl Iterator<Item = Particle>) -> Self {
+        let particles = particles.collect::<Vec<_>>();
+
+        let particles = par_iter!(particles);
+
+        let cells = particles
+            .map(|particle| (*particle, VoronoiCell::new(particle)))
+            .collect::<HashMap<_, _>>();
+
+        Self {
+            cells: Arc::new(cells),
+        }
+    }
+
+    pub fn get(&self, particle: &Particle) -> Option<&VoronoiCell> {
+        self.cells.get(particle)
+    }
+
+    /// Returns the cached cell, or computes it if the particle is not in the cache.
+    pub fn get_or_compute(&self, particle: &Particle) -> Cow<'_, VoronoiCell> {
+        match self.cells.get(particle) {
+            Some(cell) => Cow::Borrowed(cell),
+            None => Cow::Owned(VoronoiCell::new(particle)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Particle, &VoronoiCell)> {
+        self.cells.iter()
+    }
+
+    /// Shortest distance from any of `sources` to each particle, walking between
+    /// neighboring sites. Only particles for which `within` holds are walked through.
+    pub fn graph_distances(
+        &self,
+        sources: impl Iterator<Item = Particle>,
+        within: impl Fn(&Particle) -> bool,
+    ) -> HashMap<Particle, f64> {
+        let mut distances = HashMap::new();
+        let mut open = sources
+            .filter(|particle| within(particle))
+            .map(|particle| LowestFirst { key: 0.0, particle })
+            .collect::<BinaryHeap<_>>();
+
+        while let Some(LowestFirst { key, particle }) = open.pop() {
+            if distances.contains_key(&particle) {
+                continue;
+            }
+            distances.insert(particle, key);
+            let site = particle.site();
+            for neighbor in self.get_or_compute(&particle).neighbors.iter() {
+                if distances.contains_key(neighbor) || !within(neighbor) {
+                    continue;
+                }
+                let neighbor_site = neighbor.site();
+                open.push(LowestFirst {
+                    key: key + (site.0 - neighbor_site.0).hypot(site.1 - neighbor_site.1),
+                    particle: *neighbor,
+                });
+            }
+        }
+
+        distances
+    }
+}
+
+/// Heap entry popping the particle with the lowest key first, ties broken by site.
+pub(crate) struct LowestFirst {
+    pub key: f64,
+    pub particle: Particle,
+}
+
+impl PartialEq for LowestFirst {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for LowestFirst {}
+
+impl PartialOrd for LowestFirst {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LowestFirst {
+    // Reversed because `BinaryHeap` pops the greatest entry.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (site, other_site) = (self.particle.site(), other.particle.site());
+        other
+            .key
+            .total_cmp(&self.key)
+            .then(other_site.0.total_cmp(&site.0))
+            .then(other_site.1.total_cmp(&site.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::particles;
+
+    #[test]
+    fn cells_share_an_edge_with_each_neighbor() {
+        let particles = particles(5.0, 5.0);
+        let middle = particles
+            .iter()
+            .min_by(|a, b| {
+                let distance = |particle: &Particle| {
+                    let site = particle.site();
+                    (site.0 - 2.5).hypot(site.1 - 2.5)
+                };
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap();
+        let cell = VoronoiCell::new(middle);
+
+        assert_eq!(cell.edge_lengths.len(), cell.neighbors.len());
+        assert!(cell.edge_lengths.iter().all(|length| *length > 0.0));
+        let perimeter = (0..cell.polygon.len())
+            .map(|i| (cell.polygon[i], cell.polygon[(i + 1) % cell.polygon.len()]))
+            .map(|(a, b)| (a.0 - b.0).hypot(a.1 - b.1))
+            .sum::<f64>();
+        let shared = cell.edge_lengths.iter().sum::<f64>();
+        assert!((perimeter - shared).abs() < 1e-9 * perimeter);
+    }
+
+    #[test]
+    fn graph_distances_walk_between_sites() {
+        let mut particles = particles(5.0, 1.0);
+        particles.sort_by(|a, b| a.site().0.total_cmp(&b.site().0));
+        let voronoi = VoronoiCache::from_particles(particles.iter().copied());
+        let (source, blocked) = (particles[0], particles[2]);
+
+        let on_map = |particle: &Particle| particles.contains(particle);
+        let distances = voronoi.graph_distances(std::iter::once(source), on_map);
+        assert_eq!(distances.len(), particles.len());
+        assert_eq!(distances[&source], 0.0);
+        for (particle, distance) in distances.iter() {
+            let (site, source_site) = (particle.site(), source.site());
+            assert!(*distance >= (site.0 - source_site.0).hypot(site.1 - source_site.1) - 1e-9);
+        }
+
+        // Nothing is reached past a particle that can't be walked through.
+        let distances = voronoi.graph_distances(std::iter::once(source), |particle| {
+            on_map(particle) && *particle != blocked
+        });
+        assert!(!distances.contains_key(&blocked));
+        assert!(distances
+            .keys()
+            .all(|particle| particle.site().0 < blocked.site().0));
+    }
+
+    #[test]
+    fn clones_share_the_cache() {
+        let particles = particles(3.0, 3.0);
+        let voronoi = VoronoiCache::from_particles(particles[1..].iter().copied());
+        let clone = voronoi.clone();
+        assert!(Arc::ptr_eq(&voronoi.cells, &clone.cells));
+
+        assert!(matches!(
+            voronoi.get_or_compute(&particles[1]),
+            Cow::Borrowed(_)
+        ));
+        assert!(voronoi.get(&particles[0]).is_none());
+        assert_eq!(
+            *voronoi.get_or_compute(&particles[0]),
+            VoronoiCell::new(&particles[0])
+        );
+    }
+}
+
+#[cfg(feature = "visualize")]
+mod visualization {
+    use gtk4::{cairo::Context, prelude::WidgetExt, DrawingArea};
+    use vislayers::geometry::FocusRange;
+    use worley_particle::Particle;
+
+    use super::VoronoiCache;
+
+    impl VoronoiCache {
+        /// Fills the cell of every particle with the `[r, g, b, a]` color `color` gives its
+        /// value.
+        pub(crate) fn fill_cells<'a, T: 'a>(
+            &self,
+            drawing_area: &DrawingArea,
+            ctx: &Context,
+            focus_range: &FocusRange,
+            values: impl Iterator<Item = (&'a Particle, &'a T)>,
+            color: impl Fn(&T) -> [f64; 4],
+        ) {
+            let area_width = drawing_area.width();
+            let area_height = drawing_area.height();
+
+            let rect = focus_range.to_rect(area_width as f64, area_height as f64);
+
+            for (particle, value) in values {
+                let color = color(value);
+                ctx.set_source_rgba(color[0], color[1], color[2], color[3]);
+
+                let cell = self.get_or_compute(particle);
+
+                ctx.new_path();
+                for (i, point) in cell.polygon.iter().enumerate() {
+                    let x = rect.map_coord_x(point.0, 0.0, area_width as f64);
+                    let y = rect.map_coord_y(point.1, 0.0, area_height as f64);
+
+                    if i == 0 {
+                        ctx.move_to(x, y);
+                    } else {
+                        ctx.line_to(x, y);
+                    }
+                }
+
+                ctx.fill().expect("Failed to fill Voronoi cell");
+            }
+        }
+    }
+}